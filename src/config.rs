@@ -1,30 +1,437 @@
 use crate::core::Core;
 use crate::error::CustomError;
+use crate::git_extraction::extraction::{expand_folder_glob, is_glob};
+use crate::subsystem_mapping::anonymize_salt_from_env;
 use notify::{DebouncedEvent, Op, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 // -- Structs --
 
 /// Stores the configuration about the repository to scrap (and how to scrap them)
 /// Each Target is a repository/local folder
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 pub struct SiostamConfig {
     pub(crate) suffix: String,
     pub(crate) targets: Vec<Target>,
+
+    /// When enabled, the file names are compared to the suffix regardless of their case
+    #[serde(default)]
+    pub(crate) case_insensitive_suffix: bool,
+
+    /// File names matched as a whole, on top of the suffix (e.g. `subsystems.toml` without prefix)
+    #[serde(default)]
+    pub(crate) exact_names: Vec<String>,
+
+    /// When enabled, every dependency must explain itself with a `why`
+    #[serde(default)]
+    pub(crate) require_dependency_why: bool,
+
+    /// When enabled, any parent_system or dependency pointing to an unknown id is an error
+    #[serde(default)]
+    pub(crate) strict_links: bool,
+
+    /// When enabled, a file which can't be read or parsed fails the graph construction,
+    /// instead of being left out of the graph
+    #[serde(default)]
+    pub(crate) strict_files: bool,
+
+    /// When enabled, the graph construction fails when no file matches the suffix
+    #[serde(default)]
+    pub(crate) require_files: bool,
+
+    /// When enabled, the systems linked to nothing are logged as warnings
+    #[serde(default)]
+    pub(crate) warn_orphan_systems: bool,
+
+    /// When not empty, only these systems (with their descendants) are kept in the graph
+    #[serde(default)]
+    pub(crate) include_systems: Vec<String>,
+
+    /// When enabled, the `repo_name` and `path` of the items are hashed in the outputs,
+    /// to publish the graph without revealing how the repositories are organized.
+    /// The hashes are salted with SIOSTAM_ANONYMIZE_SALT, which must then be set
+    #[serde(default)]
+    pub(crate) anonymize_paths: bool,
+
+    /// Separator of the ids, e.g. `.` for `team.service`: the systems and subsystems without
+    /// parent are put in a system named after the first segment of their id
+    pub(crate) group_by_id_prefix: Option<String>,
+
+    /// When enabled, the systems and subsystems without how-to get the ones of their closest
+    /// parent system having some
+    #[serde(default)]
+    pub(crate) inherit_howtos: bool,
+
+    /// Limits protecting the server from an unrenderable graph (e.g. with a too broad suffix)
+    pub(crate) max_systems: Option<usize>,
+    pub(crate) max_subsystems: Option<usize>,
+    pub(crate) max_edges: Option<usize>,
+
+    /// Limit in megabytes of what is received when cloning/fetching a repository,
+    /// protecting the disk of data/ from a huge repository
+    pub(crate) max_repo_size: Option<u64>,
+
+    /// Keep only the files whose last commit matches, in the `[commit_filter]` section
+    pub(crate) commit_filter: Option<CommitFilter>,
+
+    /// Base name of the one-shot outputs, `output` by default: `<output_name>.json`...
+    pub(crate) output_name: Option<String>,
+
+    /// How the graph is drawn, in the `[render]` section
+    #[serde(default)]
+    pub(crate) render: RenderConfig,
+}
+
+/// Select the files of the git targets by their last commit, e.g. for an audit of a team's
+/// recent changes. Both criteria must match when both are set.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct CommitFilter {
+    /// Part of the author name or email, case insensitive
+    pub(crate) author: Option<String>,
+    /// Date (`2020-01-31`) or date and time (`2020-01-31T12:00:00`, UTC) of the oldest commit
+    pub(crate) since: Option<String>,
+}
+
+impl CommitFilter {
+    /// The `since` date, as seconds since the Unix epoch
+    pub fn since_timestamp(&self) -> Result<Option<i64>, CustomError> {
+        let since = match self.since.as_ref() {
+            Some(since) => since.trim(),
+            None => return Ok(None),
+        };
+
+        // A bare date starts at midnight
+        let with_time = if since.len() == 10 {
+            format!("{}T00:00:00", since)
+        } else {
+            since.to_owned()
+        };
+        let time = humantime::parse_rfc3339_weak(with_time.as_str()).map_err(|err| {
+            CustomError::new(format!(
+                "While parsing `commit_filter.since` `{}`: {}",
+                since, err
+            ))
+        })?;
+        let seconds = time
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Ok(Some(seconds))
+    }
+
+    /// Whether a commit of this author at this time (seconds since the Unix epoch) is kept
+    pub fn matches(&self, author_name: &str, author_email: &str, time: i64) -> bool {
+        let author_matches = match self.author.as_ref() {
+            Some(author) => {
+                let author = author.to_lowercase();
+                author_name.to_lowercase().contains(author.as_str())
+                    || author_email.to_lowercase().contains(author.as_str())
+            }
+            None => true,
+        };
+        // The date has been checked by SiostamConfig::validate
+        let since_matches = match self.since_timestamp() {
+            Ok(Some(since)) => time >= since,
+            _ => true,
+        };
+        author_matches && since_matches
+    }
+}
+
+/// Options on how the graph is rendered as DOT/SVG. They do not change the JSON output.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct RenderConfig {
+    /// Skip the systems (clusters) and only draw the subsystems and their dependencies
+    #[serde(default)]
+    pub(crate) flat: bool,
+
+    /// Skip the DOT and SVG generation (and graphviz) when only the JSON is used
+    #[serde(default)]
+    pub(crate) disable_svg: bool,
+
+    /// Draw the SVG with a basic built-in layout instead of graphviz, which is then not needed
+    #[serde(default)]
+    pub(crate) disable_graphviz: bool,
+
+    /// Draw the subsystems without system in an `Unassigned` cluster rather than at the root
+    #[serde(default)]
+    pub(crate) unassigned_cluster: bool,
+
+    /// Direction of the whole layout. Graphviz's default (top to bottom) when missing
+    pub(crate) orientation: Option<Orientation>,
+
+    /// Font of the labels, Helvetica 10 by default
+    pub(crate) fontname: Option<String>,
+    pub(crate) fontsize: Option<u32>,
+
+    /// Longer names are cut with an ellipsis in the DOT labels. The full name stays in the tooltip
+    /// and in the JSON
+    pub(crate) max_label_length: Option<usize>,
+
+    /// Graphviz shape of the subsystems by `type`, on top of the built-in ones
+    #[serde(default)]
+    pub(crate) shapes: HashMap<String, String>,
+
+    /// Graphviz colors and style of the systems and subsystems by `status`, on top of the
+    /// built-in `active`, `deprecated` and `planned`
+    #[serde(default)]
+    pub(crate) statuses: HashMap<String, StatusStyle>,
+
+    /// Appearance of every system (cluster), in the `[render.cluster]` section
+    #[serde(default)]
+    pub(crate) cluster: ClusterStyle,
+}
+
+/// How the systems are drawn. Missing attributes keep the default light grey box,
+/// and the style of a `status` applies on top of them.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct ClusterStyle {
+    /// Graphviz style, such as `rounded,filled`
+    pub(crate) style: Option<String>,
+    /// Color of the background
+    pub(crate) bgcolor: Option<String>,
+    /// Where the name is written, `t` (top) or `b` (bottom)
+    pub(crate) labelloc: Option<LabelLocation>,
+}
+
+/// The values of graphviz's `labelloc` for the clusters
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum LabelLocation {
+    #[serde(rename = "t")]
+    Top,
+    #[serde(rename = "b")]
+    Bottom,
+}
+
+impl LabelLocation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LabelLocation::Top => "t",
+            LabelLocation::Bottom => "b",
+        }
+    }
+}
+
+/// How the systems and subsystems of a `status` are drawn. Missing attributes keep the default.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StatusStyle {
+    /// Color of the border
+    pub(crate) color: Option<String>,
+    /// Color of the background
+    pub(crate) fillcolor: Option<String>,
+    /// Graphviz style, such as `filled,dashed`
+    pub(crate) style: Option<String>,
+}
+
+/// The values of graphviz's `rankdir`. Anything else is refused when reading the configuration.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum Orientation {
+    TB,
+    LR,
+    BT,
+    RL,
+}
+
+impl Orientation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Orientation::TB => "TB",
+            Orientation::LR => "LR",
+            Orientation::BT => "BT",
+            Orientation::RL => "RL",
+        }
+    }
 }
 
 /// Contains data about a repository/local folder to scrap.
-/// Url and branch are used in "git repository" setting (when folder is not defined)
+/// Url and branch are used in "git repository" setting (when folder is not defined).
+/// Without branch, the default branch of the remote is used
 /// Folder points a local folder
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 pub struct Target {
     pub(crate) url: Option<String>,
-    pub(crate) branch: Option<String>,
+    pub(crate) branch: Option<Branch>,
     pub(crate) folder: Option<String>,
+    /// A .tar, .tar.gz/.tgz or .zip file, unpacked in a temporary folder and walked like a folder
+    pub(crate) archive: Option<String>,
+
+    /// Also initialize and update the git submodules of the repository
+    #[serde(default)]
+    pub(crate) recurse_submodules: bool,
+}
+
+impl Target {
+    /// The branch names to try, in order. Empty when omitted: the default branch of the remote
+    /// is used instead
+    pub fn branches(&self) -> &[String] {
+        match &self.branch {
+            Some(branch) => branch.candidates(),
+            None => &[],
+        }
+    }
+}
+
+/// Either a branch name or a list of fallback branch names (e.g. `["main", "master"]`).
+/// With a list, the first branch existing on the remote is used.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Branch {
+    Single(String),
+    Fallbacks(Vec<String>),
+}
+
+impl Branch {
+    /// The branch names to try, in order
+    pub fn candidates(&self) -> &[String] {
+        match self {
+            Branch::Single(name) => std::slice::from_ref(name),
+            Branch::Fallbacks(names) => names.as_slice(),
+        }
+    }
+}
+
+/// Whether a boolean environment variable is enabled, with `1` or `true` in any case
+pub fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Make sure the base name of the outputs (`output_name` or `--name`) stays inside `data/`
+pub fn check_output_name(name: &str) -> Result<(), CustomError> {
+    if name.trim().is_empty() || name.contains(&['/', '\\'][..]) {
+        return Err(CustomError::new(format!(
+            "The output name `{}` must be a file name, without folder",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl RenderConfig {
+    /// Whether only the JSON is generated, see `disable_svg`
+    pub fn is_svg_disabled(&self) -> bool {
+        self.disable_svg
+    }
+}
+
+impl SiostamConfig {
+    /// Base name of the files written by the one-shot mode
+    pub fn output_name(&self) -> &str {
+        self.output_name.as_deref().unwrap_or("output")
+    }
+
+    /// How the graph is drawn, from the `[render]` section
+    pub fn render(&self) -> &RenderConfig {
+        &self.render
+    }
+
+    /// Refuse the configurations which can only produce an empty graph
+    pub fn validate(&self) -> Result<(), CustomError> {
+        if self.suffix.trim().is_empty() {
+            return Err(CustomError::new(
+                "The `suffix` is empty: it must select the subsystem files, e.g. `subsystems.toml`"
+                    .to_owned(),
+            ));
+        }
+        if self.targets.is_empty() {
+            return Err(CustomError::new(
+                "No target: add at least one `[[targets]]` with a `url` or a `folder`".to_owned(),
+            ));
+        }
+        if let Some(filter) = self.commit_filter.as_ref() {
+            filter.since_timestamp()?;
+        }
+        // A random salt would change the hashes, and the content_hash of the graph, on each restart
+        if self.anonymize_paths && anonymize_salt_from_env().is_none() {
+            return Err(CustomError::new(
+                "`anonymize_paths` requires a secret salt in SIOSTAM_ANONYMIZE_SALT, \
+                 so that the hashes stay the same across restarts"
+                    .to_owned(),
+            ));
+        }
+        check_output_name(self.output_name())
+    }
+
+    /// Whether the same files are extracted with the other configuration,
+    /// so that the files parsed with this one may be reused
+    pub fn same_extraction(&self, other: &SiostamConfig) -> bool {
+        self.suffix == other.suffix
+            && self.targets == other.targets
+            && self.case_insensitive_suffix == other.case_insensitive_suffix
+            && self.exact_names == other.exact_names
+            && self.max_repo_size == other.max_repo_size
+            && self.commit_filter == other.commit_filter
+    }
+
+    /// Whether the other configuration gives the same graph, e.g. when only `output_name` changed
+    pub fn same_graph(&self, other: &SiostamConfig) -> bool {
+        // Listed field by field so that a new field has to be sorted out here
+        let SiostamConfig {
+            suffix: _,
+            targets: _,
+            case_insensitive_suffix: _,
+            exact_names: _,
+            max_repo_size: _,
+            commit_filter: _,
+            require_dependency_why,
+            strict_links,
+            strict_files,
+            require_files,
+            warn_orphan_systems,
+            include_systems,
+            anonymize_paths,
+            group_by_id_prefix,
+            inherit_howtos,
+            max_systems,
+            max_subsystems,
+            max_edges,
+            output_name: _,
+            render,
+        } = self;
+
+        self.same_extraction(other)
+            && *require_dependency_why == other.require_dependency_why
+            && *strict_links == other.strict_links
+            && *strict_files == other.strict_files
+            && *require_files == other.require_files
+            && *warn_orphan_systems == other.warn_orphan_systems
+            && *include_systems == other.include_systems
+            && *anonymize_paths == other.anonymize_paths
+            && *group_by_id_prefix == other.group_by_id_prefix
+            && *inherit_howtos == other.inherit_howtos
+            && *max_systems == other.max_systems
+            && *max_subsystems == other.max_subsystems
+            && *max_edges == other.max_edges
+            && *render == other.render
+    }
+
+    /// One-line description of what is going to be extracted
+    pub fn summary(&self) -> String {
+        let folders = self.targets.iter().filter(|t| t.folder.is_some()).count();
+        let archives = self
+            .targets
+            .iter()
+            .filter(|t| t.folder.is_none() && t.archive.is_some())
+            .count();
+        format!(
+            "{} target(s): {} git, {} folder(s), {} archive(s); suffix={}",
+            self.targets.len(),
+            self.targets.len() - folders - archives,
+            folders,
+            archives,
+            self.suffix
+        )
+    }
 }
 
 // -- Methods: reading the configuration --
@@ -36,13 +443,17 @@ pub fn read_config_in_workdir(path: &str) -> Result<SiostamConfig, CustomError>
     })?;
 
     // Parse the resulting string
-    let toml = toml::from_str(config.as_str()).map_err(|err| {
+    let toml: SiostamConfig = toml::from_str(config.as_str()).map_err(|err| {
         CustomError::new(format!(
             "While parsing config file `{}` as TOML: {}",
             path, err
         ))
     })?;
 
+    // Check it makes sense
+    toml.validate()
+        .map_err(|err| CustomError::new(format!("Invalid config file `{}`: {}", path, err)))?;
+
     // Yay, a complete config
     Ok(toml)
 }
@@ -99,3 +510,211 @@ fn watch(access_to_core: Arc<Core>, path: &str) -> notify::Result<Receiver<Debou
         }
     }
 }
+
+// -- Methods: watching the local folders --
+
+/// Watch the configuration and the `folder` targets, and call `on_change` after each change.
+/// The folders are listed once: a folder added to the configuration is only watched after a restart.
+pub fn watch_local_targets(
+    config_path: &str,
+    mut on_change: impl FnMut(),
+) -> Result<(), CustomError> {
+    let config = read_config_in_workdir(config_path)?;
+
+    // The changes are grouped to regenerate only once when many files are saved at once
+    let (tx, rx) = channel();
+    let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_millis(500))
+        .map_err(|err| CustomError::new(format!("While creating the watcher: {}", err)))?;
+
+    watcher
+        .watch(config_path, RecursiveMode::NonRecursive)
+        .map_err(|err| {
+            CustomError::new(format!(
+                "While watching config file `{}`: {}",
+                config_path, err
+            ))
+        })?;
+    // The folder patterns are expanded once: the folders created later are not watched
+    let mut folders: Vec<PathBuf> = Vec::new();
+    for folder in config.targets.iter().filter_map(|t| t.folder.as_ref()) {
+        if is_glob(folder) {
+            folders.append(&mut expand_folder_glob(folder)?);
+        } else {
+            folders.push(PathBuf::from(folder));
+        }
+    }
+    for folder in folders.iter() {
+        watcher
+            .watch(folder, RecursiveMode::Recursive)
+            .map_err(|err| {
+                CustomError::new(format!(
+                    "While watching folder `{}`: {}",
+                    folder.display(),
+                    err
+                ))
+            })?;
+    }
+    if folders.is_empty() {
+        log::warn!("No `folder` target: only the configuration is watched");
+    }
+
+    // The outputs are written in data/: they must not trigger another generation
+    let data = fs::canonicalize("data").ok();
+    let is_output = |path: &Path| match (data.as_ref(), fs::canonicalize(path)) {
+        (Some(data), Ok(path)) => path.starts_with(data),
+        _ => false,
+    };
+
+    log::info!("Watching for changes, press Ctrl+C to stop");
+    loop {
+        match rx.recv() {
+            Ok(DebouncedEvent::Create(path))
+            | Ok(DebouncedEvent::Write(path))
+            | Ok(DebouncedEvent::Remove(path))
+            | Ok(DebouncedEvent::Rename(_, path)) => {
+                if !is_output(&path) {
+                    log::info!("Change detected in {}", path.display());
+                    on_change();
+                }
+            }
+            Ok(DebouncedEvent::Rescan) => on_change(),
+            Ok(DebouncedEvent::Error(err, path)) => {
+                log::error!("Watch error on {:?}: {}", path, err)
+            }
+            Ok(event) => log::trace!("{:?}", event),
+            Err(err) => {
+                return Err(CustomError::new(format!(
+                    "While waiting for changes: {}",
+                    err
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_single_or_fallbacks() {
+        let config: SiostamConfig = toml::from_str(
+            r#"
+            suffix = "subsystems.toml"
+
+            [[targets]]
+            url = "https://github.com/siostam/siostam"
+            branch = "master"
+
+            [[targets]]
+            url = "https://github.com/siostam/ngx-siostam"
+            branch = ["main", "master"]
+            "#,
+        )
+        .unwrap();
+
+        let candidates: Vec<&[String]> = config
+            .targets
+            .iter()
+            .map(|t| t.branch.as_ref().unwrap().candidates())
+            .collect();
+        assert_eq!(candidates[0], ["master"]);
+        assert_eq!(candidates[1], ["main", "master"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |toml: &str| toml::from_str::<SiostamConfig>(toml).unwrap();
+
+        let no_target = config("suffix = \"subsystems.toml\"\ntargets = []");
+        assert!(no_target.validate().is_err());
+
+        let empty_suffix = config("suffix = \" \"\n[[targets]]\nfolder = \"src\"");
+        assert!(empty_suffix.validate().is_err());
+
+        let nested_output = config(
+            "suffix = \"subsystems.toml\"\noutput_name = \"a/b\"\n[[targets]]\nfolder = \"src\"",
+        );
+        assert!(nested_output.validate().is_err());
+        assert!(check_output_name("../../etc/x").is_err());
+        assert!(check_output_name("payments").is_ok());
+
+        let anonymized = config(
+            "suffix = \"subsystems.toml\"\nanonymize_paths = true\n[[targets]]\nfolder = \"src\"",
+        );
+        assert_eq!(
+            anonymized.validate().is_ok(),
+            anonymize_salt_from_env().is_some()
+        );
+
+        let valid = config(
+            "suffix = \"subsystems.toml\"\n[[targets]]\nfolder = \"src\"\n[[targets]]\nurl = \"u\"",
+        );
+        assert!(valid.validate().is_ok());
+        assert_eq!(
+            valid.summary(),
+            "2 target(s): 1 git, 1 folder(s), 0 archive(s); suffix=subsystems.toml"
+        );
+    }
+
+    #[test]
+    fn test_env_flag() {
+        env::set_var("SIOSTAM_TEST_ENV_FLAG_ON", "TRUE");
+        env::set_var("SIOSTAM_TEST_ENV_FLAG_OFF", "yes");
+        assert!(env_flag("SIOSTAM_TEST_ENV_FLAG_ON"));
+        assert!(!env_flag("SIOSTAM_TEST_ENV_FLAG_OFF"));
+        assert!(!env_flag("SIOSTAM_TEST_ENV_FLAG_MISSING"));
+    }
+
+    #[test]
+    fn test_commit_filter() {
+        let filter = CommitFilter {
+            author: Some("payments".to_owned()),
+            since: Some("2020-01-31".to_owned()),
+        };
+        assert_eq!(filter.since_timestamp().unwrap(), Some(1_580_428_800));
+
+        let after = 1_580_428_800;
+        assert!(filter.matches("Jane", "jane@Payments.example.com", after));
+        assert!(!filter.matches("Jane", "jane@example.com", after));
+        assert!(!filter.matches("Jane", "jane@payments.example.com", after - 1));
+
+        let invalid = CommitFilter {
+            since: Some("last week".to_owned()),
+            ..CommitFilter::default()
+        };
+        assert!(invalid.since_timestamp().is_err());
+    }
+
+    #[test]
+    fn test_render_orientation() {
+        let config: SiostamConfig =
+            toml::from_str("suffix = \"s.toml\"\ntargets = []\n[render]\norientation = \"LR\"")
+                .unwrap();
+        assert_eq!(config.render.orientation, Some(Orientation::LR));
+
+        let invalid: Result<SiostamConfig, _> =
+            toml::from_str("suffix = \"s.toml\"\ntargets = []\n[render]\norientation = \"XY\"");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_same_extraction_and_graph() {
+        let config = |options: &str| -> SiostamConfig {
+            toml::from_str(&format!("suffix = \"s.toml\"\n{}\ntargets = []", options)).unwrap()
+        };
+        let base = config("");
+
+        let renamed = config("output_name = \"payments\"");
+        assert!(base.same_graph(&renamed));
+
+        let strict = config("strict_links = true");
+        assert!(base.same_extraction(&strict));
+        assert!(!base.same_graph(&strict));
+
+        let other_suffix: SiostamConfig =
+            toml::from_str("suffix = \"t.toml\"\ntargets = []").unwrap();
+        assert!(!base.same_extraction(&other_suffix));
+        assert!(!base.same_graph(&other_suffix));
+    }
+}
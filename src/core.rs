@@ -1,15 +1,27 @@
-use crate::config::{read_config_in_workdir, SiostamConfig};
+use crate::config::{read_config_in_workdir, RenderConfig, SiostamConfig};
 use crate::error::CustomError;
-use crate::subsystem_mapping::{Graph, GraphRepresentation};
-use std::ops::Deref;
+use crate::subsystem_mapping::cache::TargetCache;
+use crate::subsystem_mapping::cancellation::Cancellation;
+use crate::subsystem_mapping::issues::GraphIssues;
+use crate::subsystem_mapping::{
+    Direction, Graph, GraphRepresentation, SvgDimensions, HIDDEN_BY_ANONYMIZATION,
+};
+use arc_swap::ArcSwap;
+use humantime::{format_duration, format_rfc3339_seconds, parse_duration};
+use serde_derive::Serialize;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Store the metadata required for update checking
 pub struct Updatable<T> {
     version: usize,
     last_check: Instant,
+    /// Wall-clock time of the last successful update, to display freshness to users.
+    /// None until a first version has been built
+    last_success: Option<SystemTime>,
     storage: T,
     has_been_acknowledged: bool,
 }
@@ -21,11 +33,12 @@ where
     /// Store the first version for this storage
     ///
     /// The first version is automatically acknowledged because it is not possible to rely
-    /// on a previous version
+    /// on a previous version. It is not a success yet, see `succeeded`
     pub fn from(storage: T) -> Updatable<T> {
         Updatable {
             version: 0,
             last_check: Instant::now(),
+            last_success: None,
             storage,
 
             // The first version is automatically acknowledged because it is not possible
@@ -47,6 +60,38 @@ where
         }
 
         self.last_check = Instant::now();
+        self.last_success = Some(SystemTime::now());
+    }
+
+    /// Same as `update` for a storage read without lock: the next state is returned, to be swapped
+    /// in, instead of modifying this one. An identical new version is stored as well, with the
+    /// same version number
+    pub fn updated(&self, new_version: T) -> Updatable<T> {
+        let is_different = new_version != self.storage;
+
+        Updatable {
+            version: if is_different {
+                self.version + 1
+            } else {
+                self.version
+            },
+            last_check: Instant::now(),
+            last_success: Some(SystemTime::now()),
+            storage: new_version,
+            has_been_acknowledged: self.has_been_acknowledged && !is_different,
+        }
+    }
+
+    /// Mark the first version as the result of a successful update, e.g. the initial build
+    pub fn succeeded(mut self) -> Updatable<T> {
+        self.last_success = Some(SystemTime::now());
+        self
+    }
+
+    /// RFC 3339 date of the last successful update, None before the first one
+    pub fn last_update(&self) -> Option<String> {
+        self.last_success
+            .map(|time| format_rfc3339_seconds(time).to_string())
     }
 
     pub fn acknowledge(&mut self) {
@@ -54,10 +99,125 @@ where
     }
 }
 
+/// Metadata about the current graph, computed once per render
+#[derive(Serialize)]
+pub struct GraphMetadata {
+    /// Incremented on each change since the server started
+    version: usize,
+    /// Hash of the graph content, identical for identical graphs even across restarts
+    content_hash: String,
+    /// RFC 3339 date of the last successful update, null before the first one
+    last_update: Option<String>,
+    svg: SvgDimensions,
+}
+
+/// Issues of the current graph, plus the error of the last update if it failed
+/// (for instance when a file could not be parsed)
+#[derive(Serialize)]
+pub struct IssuesReport {
+    #[serde(flatten)]
+    issues: GraphIssues,
+    build_error: Option<String>,
+}
+
+/// Liveness of the server, as seen by the monitoring
+#[derive(Serialize)]
+pub struct Health {
+    /// `ok`, or `degraded` if the last configuration reload failed
+    status: &'static str,
+    /// False until the first graph is built, when started in background
+    ready: bool,
+    config_error: Option<String>,
+    /// Incremented on each change of the configuration since the server started
+    config_version: usize,
+    /// Incremented on each change of the graph since the server started
+    graph_version: usize,
+    /// RFC 3339 date of the last successful graph update, null before the first one
+    last_update: Option<String>,
+    /// Suffix of the subsystem files of the current configuration
+    suffix: String,
+    /// Number of targets of the current configuration
+    target_count: usize,
+}
+
+/// Read how many times the first graph construction is retried, none by default
+fn get_initial_build_retries() -> u32 {
+    match env::var("SIOSTAM_INITIAL_BUILD_RETRIES") {
+        Ok(value) => value.parse::<u32>().unwrap_or_else(|_| {
+            log::error!(
+                "SIOSTAM_INITIAL_BUILD_RETRIES must be a positive integer or 0, got `{}`",
+                value
+            );
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+/// Read the delay before the first retry of the first graph construction, 10 seconds by default
+fn get_initial_build_retry_delay() -> Duration {
+    let duration = match env::var("SIOSTAM_INITIAL_BUILD_RETRY_DELAY") {
+        Ok(duration) => duration,
+        Err(_) => return Duration::from_secs(10),
+    };
+    parse_duration(duration.as_str()).unwrap_or_else(|e| {
+        log::error!(
+            "While parsing SIOSTAM_INITIAL_BUILD_RETRY_DELAY env var: {}",
+            e
+        );
+        Duration::from_secs(10)
+    })
+}
+
+/// Construct the graph, retrying up to `retries` times on failure.
+/// The delay between two attempts doubles each time
+fn construct_with_retries(
+    config: &SiostamConfig,
+    cache: &mut TargetCache,
+    retries: u32,
+    delay: Duration,
+) -> Result<Graph, CustomError> {
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        match Graph::construct_incrementally(config, cache, &Cancellation::default()) {
+            Ok(graph) => return Ok(graph),
+            Err(err) if attempt <= retries => {
+                log::warn!(
+                    "While constructing graph (attempt {} of {}): {}. Retrying in {}",
+                    attempt,
+                    retries + 1,
+                    err,
+                    format_duration(delay)
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(CustomError::new(format!(
+                    "While constructing graph: {}",
+                    err
+                )))
+            }
+        }
+    }
+}
+
+/// The graph served until a first one is built
+fn empty_representation() -> Result<GraphRepresentation, CustomError> {
+    // Nothing to draw yet: graphviz is not needed for it
+    let render = RenderConfig {
+        disable_svg: true,
+        ..RenderConfig::default()
+    };
+    GraphRepresentation::from(Graph::empty(), &render)
+}
+
 /// Core holds all the information on the graph and whether an update is required
 /// Every update and every access to data goes through the core
 ///
-/// Dev: later, it may hold cache and drive partial update to the graph
+/// It holds the parsed files of each repository so the unchanged ones are not fetched again
 pub struct Core {
     /// How frequently we update the graph (if the file changes, this event is bypassed)
     interval_between_updates: Duration,
@@ -65,10 +225,26 @@ pub struct Core {
     config_path: String,
     /// The current configuration
     config: RwLock<Updatable<SiostamConfig>>,
-    /// The current graph data
-    graph: RwLock<Updatable<GraphRepresentation>>,
+    /// The current graph data. It is read without lock and replaced as a whole by each update
+    graph: ArcSwap<Updatable<GraphRepresentation>>,
     /// Is a graph update in progress
     is_graph_updating: Arc<Mutex<()>>,
+    /// The parsed files of the previous update, by repository
+    cache: Mutex<TargetCache>,
+    /// The error of the last configuration reload, if it failed
+    config_error: RwLock<Option<String>>,
+    /// The error of the last graph update, if it failed
+    build_error: RwLock<Option<String>>,
+    /// A refresh has been asked for, without waiting for the interval
+    refresh_requested: AtomicBool,
+    /// When the last refresh has been accepted, to limit how often they are done
+    last_refresh: Mutex<Option<Instant>>,
+    /// Whether a graph has been built, false until the first build when started in background
+    ready: AtomicBool,
+    /// The configuration changed what is extracted: the parsed files can't be reused
+    targets_changed: AtomicBool,
+    /// Cancels the graph update in progress, once a new configuration makes it stale
+    current_build: Mutex<Cancellation>,
 }
 
 impl Core {
@@ -76,25 +252,95 @@ impl Core {
     pub fn new(config_path: &str, interval_between_updates: Duration) -> Result<Core, CustomError> {
         // Retrieve the list of all remotes to fetch from the config
         let config: SiostamConfig = read_config_in_workdir(config_path)?;
+        log::info!("Configuration: {}", config.summary());
+
+        // A transient failure (e.g. the git host being unreachable) must not prevent the start
+        let mut cache = TargetCache::default();
+        let graph = construct_with_retries(
+            &config,
+            &mut cache,
+            get_initial_build_retries(),
+            get_initial_build_retry_delay(),
+        )?;
 
-        let graph = Graph::construct_from_config(&config)
-            .map_err(|err| CustomError::new(format!("While constructing graph: {}", err)))?;
+        let graph_representation = GraphRepresentation::from(graph, &config.render)?;
+
+        Ok(Core::from_parts(
+            config_path,
+            interval_between_updates,
+            config,
+            cache,
+            graph_representation,
+            true,
+        ))
+    }
 
-        let graph_representation = GraphRepresentation::from(graph)?;
+    /// Read the config and return right away with an empty graph, to open the port quickly.
+    /// The first graph is built by the first check for update, `is_ready` tells when it is there
+    pub fn new_in_background(
+        config_path: &str,
+        interval_between_updates: Duration,
+    ) -> Result<Core, CustomError> {
+        let config: SiostamConfig = read_config_in_workdir(config_path)?;
+        log::info!("Configuration: {}", config.summary());
 
-        Ok(Core {
+        let core = Core::from_parts(
+            config_path,
+            interval_between_updates,
+            config,
+            TargetCache::default(),
+            empty_representation()?,
+            false,
+        );
+        core.refresh_requested.store(true, Ordering::SeqCst);
+        Ok(core)
+    }
+
+    fn from_parts(
+        config_path: &str,
+        interval_between_updates: Duration,
+        config: SiostamConfig,
+        cache: TargetCache,
+        graph_representation: GraphRepresentation,
+        ready: bool,
+    ) -> Core {
+        Core {
             interval_between_updates,
             config_path: config_path.to_string(),
             config: RwLock::from(Updatable::from(config)),
-            graph: RwLock::from(Updatable::from(graph_representation)),
+            graph: ArcSwap::from_pointee(if ready {
+                Updatable::from(graph_representation).succeeded()
+            } else {
+                Updatable::from(graph_representation)
+            }),
             is_graph_updating: Arc::new(Mutex::from(())),
-        })
+            cache: Mutex::from(cache),
+            config_error: RwLock::from(None),
+            build_error: RwLock::from(None),
+            refresh_requested: AtomicBool::new(false),
+            last_refresh: Mutex::from(None),
+            ready: AtomicBool::new(ready),
+            targets_changed: AtomicBool::new(false),
+            current_build: Mutex::from(Cancellation::default()),
+        }
     }
 
     // -- Updates --
 
     /// Check for a new version of the configuration. Usually triggered by a change in file
+    /// The failure is kept so it can be reported to the clients
     pub fn reload_config(&self) -> Result<(), CustomError> {
+        let result = self.read_and_store_config();
+
+        let mut config_error = self.config_error.write().map_err(|e| {
+            CustomError::new(format!("While storing the configuration error: {}", e))
+        })?;
+        *config_error = result.as_ref().err().map(|err| err.message.clone());
+
+        result
+    }
+
+    fn read_and_store_config(&self) -> Result<(), CustomError> {
         let config: SiostamConfig = read_config_in_workdir(self.config_path.as_str())?;
 
         let mut pointer_to_config = self
@@ -103,7 +349,26 @@ impl Core {
             .map_err(|err| CustomError::new(format!("While reloading configuration: {}", err)))?;
 
         log::debug!("New config: {:?}", config);
+
+        // Only redo what the changes require: the parsed files are kept when the same files are
+        // extracted, and the graph is kept when it would be the same
+        let previous = &pointer_to_config.storage;
+        if !previous.same_extraction(&config) {
+            self.targets_changed.store(true, Ordering::SeqCst);
+        }
+        let same_graph = previous.same_graph(&config);
+        let was_acknowledged = pointer_to_config.has_been_acknowledged;
         (*pointer_to_config).update(config);
+        if same_graph && was_acknowledged {
+            log::info!("The configuration changed without effect on the graph");
+            (*pointer_to_config).acknowledge();
+        } else if !same_graph {
+            // The graph being built is already outdated: the next check starts a new one
+            let current_build = self.current_build.lock().map_err(|e| {
+                CustomError::new(format!("While accessing the graph update: {}", e))
+            })?;
+            current_build.cancel();
+        }
 
         Ok(())
     }
@@ -123,63 +388,207 @@ impl Core {
         // Do it in another thread
         thread::spawn(move || {
             log::info!("Starting graph update");
-            match core.upgrade_graph() {
-                Ok(()) => log::info!("Graph update complete"),
+            let cancellation = Cancellation::default();
+            let result = core.upgrade_graph(&cancellation);
+            match result.as_ref() {
+                Ok(()) => {
+                    core.ready.store(true, Ordering::SeqCst);
+                    log::info!("Graph update complete")
+                }
+                // A newer configuration is on its way: not worth reporting
+                Err(_) if cancellation.is_cancelled() => {
+                    log::info!("Graph update cancelled by a newer configuration");
+                    return;
+                }
                 Err(err) => log::error!("While updating graph: {}", err),
             }
+
+            // Keep the error to display it to the users
+            match core.build_error.write() {
+                Ok(mut build_error) => *build_error = result.err().map(|err| err.message),
+                Err(err) => log::error!("While storing the graph update error: {}", err),
+            }
         });
 
         Ok(())
     }
 
+    /// Ask for an update right now, unless another one has been accepted less than
+    /// `min_interval` ago. In this case, returns how long to wait before the next one
+    pub fn request_refresh(
+        core: Arc<Core>,
+        min_interval: Duration,
+    ) -> Result<Option<Duration>, CustomError> {
+        {
+            let mut last_refresh = core.last_refresh.lock().map_err(|e| {
+                CustomError::new(format!("While accessing the last refresh: {}", e))
+            })?;
+
+            if let Some(last) = *last_refresh {
+                let elapsed = last.elapsed();
+                if elapsed < min_interval {
+                    return Ok(Some(min_interval - elapsed));
+                }
+            }
+            *last_refresh = Some(Instant::now());
+        }
+
+        core.refresh_requested.store(true, Ordering::SeqCst);
+        Core::check_for_graph_update(core)?;
+        Ok(None)
+    }
+
     fn is_graph_update_required(&self) -> Result<bool, CustomError> {
         let config = self.config.read().map_err(|e| {
             CustomError::new(format!("While accessing the in-memory config: {}", e))
         })?;
 
-        let graph = self
-            .graph
-            .read()
-            .map_err(|e| CustomError::new(format!("While accessing the in-memory graph: {}", e)))?;
+        let graph = self.graph.load();
 
-        // If the config changed or if the graph has been updated since a while, "yes, please update"
+        // If the config changed, if a refresh has been asked for or if the graph has been updated
+        // since a while, "yes, please update"
         Ok(!config.has_been_acknowledged
+            || self.refresh_requested.load(Ordering::SeqCst)
             || graph.last_check.elapsed() > self.interval_between_updates)
     }
 
+    /// The error of the last configuration reload, if it failed
+    pub fn config_error(&self) -> Result<Option<String>, CustomError> {
+        let config_error = self.config_error.read().map_err(|e| {
+            CustomError::new(format!("While accessing the configuration error: {}", e))
+        })?;
+
+        self.published_error(config_error.clone())
+    }
+
+    /// The error of the last graph update, if it failed
+    pub fn build_error(&self) -> Result<Option<String>, CustomError> {
+        let build_error = self.build_error.read().map_err(|e| {
+            CustomError::new(format!("While accessing the graph update error: {}", e))
+        })?;
+
+        self.published_error(build_error.clone())
+    }
+
+    /// The errors quote the paths of the files: with `anonymize_paths`, they are only in the logs
+    fn published_error(&self, error: Option<String>) -> Result<Option<String>, CustomError> {
+        let config = self.config.read().map_err(|e| {
+            CustomError::new(format!("While accessing the in-memory config: {}", e))
+        })?;
+
+        if config.storage.anonymize_paths {
+            Ok(error.map(|_| HIDDEN_BY_ANONYMIZATION.to_owned()))
+        } else {
+            Ok(error)
+        }
+    }
+
+    /// Read the issues of the current version of the graph
+    pub fn issues(&self) -> Result<IssuesReport, CustomError> {
+        let current = self.graph.load();
+
+        Ok(IssuesReport {
+            issues: current.storage.issues(),
+            build_error: self.build_error()?,
+        })
+    }
+
+    /// Whether a graph has been built, as opposed to the empty graph of a start in background
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Summarize the state of the server for the monitoring
+    pub fn health(&self) -> Result<Health, CustomError> {
+        let config_error = self.config_error()?;
+        let config = self.config.read().map_err(|e| {
+            CustomError::new(format!("While accessing the in-memory config: {}", e))
+        })?;
+        let graph = self.graph.load();
+
+        Ok(Health {
+            status: if config_error.is_some() {
+                "degraded"
+            } else {
+                "ok"
+            },
+            ready: self.is_ready(),
+            config_error,
+            config_version: config.version,
+            graph_version: graph.version,
+            last_update: graph.last_update(),
+            suffix: config.storage.suffix.clone(),
+            target_count: config.storage.targets.len(),
+        })
+    }
+
+    /// Version of the configuration, incremented on each change since the start
+    pub fn config_version(&self) -> Result<usize, CustomError> {
+        let config = self.config.read().map_err(|e| {
+            CustomError::new(format!("While accessing the in-memory config: {}", e))
+        })?;
+
+        Ok(config.version)
+    }
+
     pub fn version(&self) -> Result<usize, CustomError> {
-        let graph = self
-            .graph
-            .read()
-            .map_err(|e| CustomError::new(format!("While accessing the in-memory graph: {}", e)))?;
+        let graph = self.graph.load();
 
         Ok(graph.version)
     }
 
     /// Use the current config and proceed to update the whole graph
-    fn upgrade_graph(&self) -> Result<(), CustomError> {
+    /// The configuration is not locked meanwhile: a new one may cancel this update
+    fn upgrade_graph(&self, cancellation: &Cancellation) -> Result<(), CustomError> {
         if let Ok(_guard) = self.is_graph_updating.clone().lock() {
-            // Access the current config
-            let mut config = self.config.write().map_err(|e| {
-                CustomError::new(format!("While accessing the in-memory config: {}", e))
+            // Only the update holding the guard may be cancelled, and before reading the config
+            // so that a newer one can't be missed
+            let mut current_build = self
+                .current_build
+                .lock()
+                .map_err(|e| CustomError::new(format!("While storing the graph update: {}", e)))?;
+            *current_build = cancellation.clone();
+            drop(current_build);
+
+            // A refresh asked for during this update will trigger another one
+            self.refresh_requested.store(false, Ordering::SeqCst);
+
+            // Work on a copy of the current config
+            let (config, config_version) = {
+                let config = self.config.read().map_err(|e| {
+                    CustomError::new(format!("While accessing the in-memory config: {}", e))
+                })?;
+                (config.storage.clone(), config.version)
+            };
+
+            // A new configuration may change what is extracted: start from scratch
+            let mut cache = self.cache.lock().map_err(|e| {
+                CustomError::new(format!("While accessing the repositories cache: {}", e))
             })?;
+            if self.targets_changed.swap(false, Ordering::SeqCst) {
+                cache.clear();
+            }
 
             // Construct the graph
-            let graph = Graph::construct_from_config(&(*config).storage)
+            let graph = Graph::construct_incrementally(&config, &mut cache, cancellation)
                 .map_err(|err| CustomError::new(format!("While constructing graph: {}", err)))?;
 
             // Regenerate JSON/SVG
-            let graph_representation = GraphRepresentation::from(graph)?;
+            let graph_representation =
+                GraphRepresentation::from_cancellable(graph, &config.render, cancellation)?;
 
-            let mut graph_storage = self.graph.write().map_err(|e| {
-                CustomError::new(format!(
-                    "While editing the in-memory graph representation: {}",
-                    e
-                ))
+            let mut config = self.config.write().map_err(|e| {
+                CustomError::new(format!("While accessing the in-memory config: {}", e))
             })?;
 
-            (*config).acknowledge();
-            (*graph_storage).update(graph_representation);
+            // A configuration changed since the start is built by the next update
+            if config.version == config_version {
+                (*config).acknowledge();
+            }
+
+            // Only this update writes the graph: the readers get either version, never wait
+            let updated = self.graph.load().updated(graph_representation);
+            self.graph.store(Arc::new(updated));
         }
 
         Ok(())
@@ -187,23 +596,79 @@ impl Core {
 
     // -- Getters --
 
-    /// Read the current version of the graph
-    pub fn json(&self) -> Result<String, CustomError> {
-        let lock = self
-            .graph
-            .read()
-            .map_err(|e| CustomError::new(format!("While accessing the in-memory json: {}", e)))?;
+    /// Share the current version of the graph, to keep using it after an update
+    pub fn shared_graph(&self) -> Result<Arc<Graph>, CustomError> {
+        let current = self.graph.load();
+
+        Ok(current.storage.shared_graph())
+    }
+
+    /// Work on the current version of the graph, an update meanwhile does not affect it
+    pub fn read_graph<R>(&self, reader: impl FnOnce(&Graph) -> R) -> Result<R, CustomError> {
+        let current = self.graph.load();
+
+        Ok(reader(current.storage.graph()))
+    }
+
+    /// Extract the part of the current version of the graph around a subsystem.
+    /// None when no subsystem has this id
+    pub fn neighborhood(
+        &self,
+        id: &str,
+        depth: usize,
+        direction: Direction,
+    ) -> Result<Option<Arc<Graph>>, CustomError> {
+        self.read_graph(|graph| graph.neighborhood(id, depth, direction).map(Arc::new))
+    }
+
+    /// Read the current version of the graph, as a tree of systems
+    pub fn tree(&self) -> Result<String, CustomError> {
+        self.read_graph(|graph| graph.hierarchy_to_json())?
+            .map_err(|err| CustomError::new(format!("While constructing the tree: {}", err)))
+    }
+
+    /// Read the systems of the current version of the graph, without the subsystems
+    pub fn systems(&self) -> Result<String, CustomError> {
+        self.read_graph(|graph| graph.systems_to_json())?
+            .map_err(|err| CustomError::new(format!("While serializing the systems: {}", err)))
+    }
+
+    /// Read the how-to links of the current version of the graph, with their owner
+    pub fn how_tos(&self) -> Result<String, CustomError> {
+        self.read_graph(|graph| graph.how_tos_to_json())?
+            .map_err(|err| CustomError::new(format!("While serializing the how-to links: {}", err)))
+    }
+
+    /// Read the DOT source of the current version of the graph, if the SVG generation is enabled
+    pub fn dot(&self) -> Result<Option<String>, CustomError> {
+        let current = self.graph.load();
 
-        Ok(lock.deref().storage.json())
+        Ok(current.storage.dot())
     }
 
-    /// Read the current version of the graph
-    pub fn svg(&self) -> Result<String, CustomError> {
-        let lock = self
-            .graph
-            .read()
-            .map_err(|e| CustomError::new(format!("While accessing the in-memory svg: {}", e)))?;
+    /// Read the current version of the graph, if the SVG generation is enabled
+    pub fn svg(&self) -> Result<Option<String>, CustomError> {
+        let current = self.graph.load();
 
-        Ok(lock.deref().storage.svg())
+        Ok(current.storage.svg())
+    }
+
+    /// Render the current version of the graph with another graphviz layout engine
+    pub fn svg_with_engine(&self, engine: &str) -> Result<Option<String>, CustomError> {
+        let current = self.graph.load();
+
+        current.storage.svg_with_engine(engine)
+    }
+
+    /// Read the metadata of the current version of the graph
+    pub fn meta(&self) -> Result<GraphMetadata, CustomError> {
+        let current = self.graph.load();
+
+        Ok(GraphMetadata {
+            version: current.version,
+            content_hash: current.storage.content_hash(),
+            last_update: current.last_update(),
+            svg: current.storage.svg_dimensions(),
+        })
     }
 }
@@ -0,0 +1,139 @@
+use crate::config::{read_config_in_workdir, SiostamConfig};
+use crate::subsystem_mapping::dot::graphviz_bin;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Result of a single check of the environment
+enum Status {
+    Ok(String),
+    /// Not blocking, but probably worth a look
+    Warn(String),
+    Fail(String),
+}
+
+/// Check everything siostam needs to run and print a checklist.
+/// Returns false if any check failed.
+pub fn run_doctor(config_path: &str) -> bool {
+    let config = read_config_in_workdir(config_path);
+
+    let mut checks = vec![match &config {
+        Ok(config) => Status::Ok(format!(
+            "Configuration `{}` parsed ({} target(s))",
+            config_path,
+            config.targets.len()
+        )),
+        Err(err) => Status::Fail(err.to_string()),
+    }];
+    checks.push(check_graphviz(config.as_ref().ok()));
+    checks.push(check_data_writable());
+    if let Ok(config) = &config {
+        checks.push(check_git_credentials(config));
+    }
+
+    let mut success = true;
+    for check in checks.iter() {
+        match check {
+            Status::Ok(message) => println!("[ OK ] {}", message),
+            Status::Warn(message) => println!("[WARN] {}", message),
+            Status::Fail(message) => {
+                success = false;
+                println!("[FAIL] {}", message)
+            }
+        }
+    }
+    success
+}
+
+/// Graphviz must be reachable, unless the SVG generation is disabled
+fn check_graphviz(config: Option<&SiostamConfig>) -> Status {
+    if config.is_some_and(|c| c.render.disable_svg) {
+        return Status::Ok("Graphviz not needed: `disable_svg` is enabled".to_owned());
+    }
+    if config.is_some_and(|c| c.render.disable_graphviz) {
+        return Status::Ok("Graphviz not needed: `disable_graphviz` is enabled".to_owned());
+    }
+
+    let bin = graphviz_bin();
+    match Command::new(bin.as_str()).arg("-V").output() {
+        // Graphviz prints its version on stderr, other wrappers may use stdout
+        Ok(output) if output.status.success() => {
+            let stderr = String::from_utf8_lossy(output.stderr.as_slice());
+            let stdout = String::from_utf8_lossy(output.stdout.as_slice());
+            let version = if stderr.trim().is_empty() {
+                stdout
+            } else {
+                stderr
+            };
+            Status::Ok(format!("Graphviz `{}` found: {}", bin, version.trim()))
+        }
+        Ok(output) => Status::Fail(format!(
+            "Graphviz `{}` failed ({}). Set SIOSTAM_GRAPHVIZ_BIN to a working executable",
+            bin, output.status
+        )),
+        // The SVG is still drawn, with the built-in layout
+        Err(err) => Status::Warn(format!(
+            "Graphviz `{}` not found: {}. The built-in layout is used instead, \
+             install graphviz or set SIOSTAM_GRAPHVIZ_BIN for a better one",
+            bin, err
+        )),
+    }
+}
+
+/// The repositories and the outputs are written in data/
+fn check_data_writable() -> Status {
+    let probe = Path::new("data").join(".siostam-doctor");
+    let result = fs::create_dir_all("data")
+        .and_then(|_| fs::write(&probe, b"siostam"))
+        .and_then(|_| fs::remove_file(&probe));
+
+    match result {
+        Ok(()) => Status::Ok("Directory `data/` is writable".to_owned()),
+        Err(err) => Status::Fail(format!("Directory `data/` is not writable: {}", err)),
+    }
+}
+
+/// The git targets may need the SIOSTAM_GIT_* variables, and half a configuration fails later
+fn check_git_credentials(config: &SiostamConfig) -> Status {
+    let git_targets = config
+        .targets
+        .iter()
+        .filter(|t| t.folder.is_none() && t.url.is_some())
+        .count();
+    if git_targets == 0 {
+        return Status::Ok("No git target: no credentials needed".to_owned());
+    }
+
+    let is_set = |name: &str| env::var(name).is_ok_and(|v| !v.is_empty());
+    let username = is_set("SIOSTAM_GIT_HTTPS_USERNAME");
+    let password = is_set("SIOSTAM_GIT_HTTPS_PASSWORD");
+    if username != password {
+        return Status::Fail(
+            "SIOSTAM_GIT_HTTPS_USERNAME and SIOSTAM_GIT_HTTPS_PASSWORD must be set together"
+                .to_owned(),
+        );
+    }
+
+    if let Ok(private_key) = env::var("SIOSTAM_GIT_SSH_PRIVATE_KEY") {
+        if !Path::new(private_key.as_str()).exists() {
+            return Status::Fail(format!(
+                "SIOSTAM_GIT_SSH_PRIVATE_KEY points to a missing file `{}`",
+                private_key
+            ));
+        }
+        return Status::Ok(format!("{} git target(s), using an SSH key", git_targets));
+    }
+
+    if username {
+        Status::Ok(format!(
+            "{} git target(s), using HTTPS credentials",
+            git_targets
+        ))
+    } else {
+        Status::Warn(format!(
+            "{} git target(s) but no SIOSTAM_GIT_* credentials: only public repositories are reachable",
+            git_targets
+        ))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::CustomError;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Archive formats which can be used as a target
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guess the format from the extension of the file
+    fn from_path(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Unpack a .tar, .tar.gz/.tgz or .zip archive in a new temporary folder
+/// The folder is removed when the returned handle is dropped
+pub fn unpack_archive(path: &Path) -> Result<TempDir, CustomError> {
+    let format = ArchiveFormat::from_path(path).ok_or_else(|| {
+        CustomError::new(format!(
+            "Archive {} is neither a .tar, a .tar.gz/.tgz nor a .zip",
+            path.display()
+        ))
+    })?;
+    let file = File::open(path).map_err(|err| {
+        CustomError::new(format!("While opening archive {}: {}", path.display(), err))
+    })?;
+    let folder = tempfile::Builder::new()
+        .prefix("siostam-archive-")
+        .tempdir()
+        .map_err(|err| {
+            CustomError::new(format!(
+                "While creating a temporary folder for {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+
+    let result = match format {
+        ArchiveFormat::Tar => unpack_tar(file, folder.path()),
+        ArchiveFormat::TarGz => unpack_tar(GzDecoder::new(file), folder.path()),
+        ArchiveFormat::Zip => zip::ZipArchive::new(file)
+            .and_then(|mut archive| archive.extract(folder.path()))
+            .map_err(|err| err.to_string()),
+    };
+    result.map_err(|err| {
+        CustomError::new(format!(
+            "While unpacking archive {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+
+    Ok(folder)
+}
+
+/// The entries escaping the destination (e.g. `../file`) are skipped by `unpack`
+fn unpack_tar<R: Read>(reader: R, destination: &Path) -> Result<(), String> {
+    tar::Archive::new(reader)
+        .unpack(destination)
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+
+    #[test]
+    fn test_unpack_tar_gz() {
+        let source = tempfile::tempdir().unwrap();
+        let archive_path = source.path().join("services.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            Compression::default(),
+        ));
+        let content = b"[[subsystem]]\nid = \"auth\"\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "auth/auth.subsystems.toml", &content[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let folder = unpack_archive(&archive_path).unwrap();
+        let unpacked = folder.path().join("auth/auth.subsystems.toml");
+        assert_eq!(fs::read(&unpacked).unwrap(), content.to_vec());
+
+        // The temporary folder goes away with its handle
+        let path = folder.path().to_owned();
+        drop(folder);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_unknown_archive_format() {
+        let err = unpack_archive(Path::new("services.rar")).unwrap_err();
+        assert!(err.to_string().contains("services.rar"));
+    }
+}
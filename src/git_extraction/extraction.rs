@@ -1,6 +1,9 @@
-use log::info;
+use crate::error::CustomError;
+use globset::Glob;
+use ignore::WalkBuilder;
+use log::{info, warn};
+use std::fs;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 
 #[derive(Debug)]
 pub struct SubsystemFile {
@@ -8,24 +11,53 @@ pub struct SubsystemFile {
     pub path: PathBuf,
     pub relative_path: String,
     pub repo_name: String,
+    /// The folder walked: the clone, the local folder or the unpacked archive
+    pub root: PathBuf,
 }
 
-/// List all files in repository with a name ending by the given suffix
+/// List all files in repository with a name ending by the given suffix, or equal to one of the
+/// exact names
+/// The walk respects the repository's .gitignore and skips the .git folder. The other hidden
+/// files and folders (e.g. `.github/`) are walked like the rest
+/// If case_insensitive is set, `Foo.Subsystems.TOML` matches the suffix `subsystems.toml`
 pub fn extract_files_from_repo(
     repo_path: &Path,
     repo_name: &str,
     suffix: &str,
+    exact_names: &[String],
+    case_insensitive: bool,
 ) -> Vec<SubsystemFile> {
     let mut file_list: Vec<SubsystemFile> = Vec::new();
+    let normalize = |name: &str| {
+        if case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.to_owned()
+        }
+    };
+    let suffix = normalize(suffix);
+    let exact_names: Vec<String> = exact_names.iter().map(|name| normalize(name)).collect();
 
     // Recursively list all files
-    for entry in WalkDir::new(repo_path) {
-        let entry = entry.unwrap();
+    let walk = WalkBuilder::new(repo_path)
+        .hidden(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("While walking in {}: {}", repo_path.display(), err);
+                continue;
+            }
+        };
         let file_name = entry.file_name().to_string_lossy();
         let file_path = entry.path();
 
         // Ignore all files not matching the pattern specified in the configuration
-        if file_name.ends_with(suffix) {
+        let name = normalize(&file_name);
+        let is_matching = name.ends_with(suffix.as_str()) || exact_names.contains(&name);
+        if is_matching {
             info!("- {}", file_name);
             file_list.push(SubsystemFile {
                 name: file_name.to_string(),
@@ -42,9 +74,191 @@ pub fn extract_files_from_repo(
                     .to_str()
                     .map(|path| path.replace("\\", "/"))
                     .unwrap_or(String::from("Corrupted path")),
+                root: repo_path.to_path_buf(),
             });
         }
     }
 
     file_list
 }
+
+/// Whether a folder is a pattern such as `services/*/docs` rather than a path.
+/// An existing folder is taken as is, even with a name such as `docs[old]`
+pub fn is_glob(folder: &str) -> bool {
+    has_wildcard(folder) && !Path::new(folder).exists()
+}
+
+fn has_wildcard(part: &str) -> bool {
+    part.contains(&['*', '?', '['][..])
+}
+
+/// List the folders matching a pattern such as `services/*/docs`, sorted.
+/// Each part of the path is matched against the folders at this level: `*`, `?` and `[ab]`
+/// are supported, but not `**`. The hidden folders only match a part starting with `.`
+pub fn expand_folder_glob(pattern: &str) -> Result<Vec<PathBuf>, CustomError> {
+    let mut matches = vec![PathBuf::new()];
+
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !has_wildcard(&part) {
+            matches.iter_mut().for_each(|m| m.push(component));
+            continue;
+        }
+
+        let matcher = Glob::new(&part)
+            .map_err(|err| {
+                CustomError::new(format!(
+                    "While parsing folder pattern `{}`: {}",
+                    pattern, err
+                ))
+            })?
+            .compile_matcher();
+        let mut next = Vec::new();
+        for parent in matches.iter() {
+            let dir = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent.as_path()
+            };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            let mut children: Vec<PathBuf> = entries
+                .filter_map(Result::ok)
+                .filter(|e| e.path().is_dir())
+                .filter(|e| {
+                    part.starts_with('.') || !e.file_name().to_string_lossy().starts_with('.')
+                })
+                .filter(|e| matcher.is_match(e.file_name()))
+                .map(|e| parent.join(e.file_name()))
+                .collect();
+            children.sort();
+            next.extend(children);
+        }
+        matches = next;
+    }
+
+    Ok(matches.into_iter().filter(|m| m.is_dir()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_folder_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        for folder in [
+            "services/b/docs",
+            "services/a/docs",
+            "services/c",
+            "services/.git/docs",
+        ]
+        .iter()
+        {
+            fs::create_dir_all(dir.path().join(folder)).unwrap();
+        }
+
+        let pattern = format!("{}/services/*/docs", dir.path().display());
+        let matches = expand_folder_glob(pattern.as_str()).unwrap();
+        assert_eq!(
+            matches,
+            vec![
+                dir.path().join("services/a/docs"),
+                dir.path().join("services/b/docs")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_existing_folder_is_not_a_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("docs[old]");
+        assert!(is_glob(folder.to_str().unwrap()));
+
+        fs::create_dir(&folder).unwrap();
+        assert!(!is_glob(folder.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_case_insensitive_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Auth.Subsystems.TOML"), "").unwrap();
+        fs::write(dir.path().join("billing.subsystems.toml"), "").unwrap();
+
+        let count = |case_insensitive: bool| {
+            extract_files_from_repo(dir.path(), "test", "subsystems.toml", &[], case_insensitive)
+                .len()
+        };
+        assert_eq!(count(false), 1);
+        assert_eq!(count(true), 2);
+    }
+
+    #[test]
+    fn test_exact_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "auth/auth.subsystems.toml",
+            "billing/subsystems.toml",
+            "billing/Subsystems.toml",
+            "billing/other-subsystems.toml.bak",
+        ]
+        .iter()
+        {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let names = |exact_names: &[String], case_insensitive: bool| {
+            let mut names: Vec<String> = extract_files_from_repo(
+                dir.path(),
+                "test",
+                ".subsystems.toml",
+                exact_names,
+                case_insensitive,
+            )
+            .into_iter()
+            .map(|file| file.relative_path)
+            .collect();
+            names.sort();
+            names
+        };
+        let exact_names = vec!["subsystems.toml".to_owned()];
+        assert_eq!(names(&[], false), vec!["auth/auth.subsystems.toml"]);
+        assert_eq!(
+            names(&exact_names, false),
+            vec!["auth/auth.subsystems.toml", "billing/subsystems.toml"]
+        );
+        assert_eq!(names(&exact_names, true).len(), 3);
+    }
+
+    #[test]
+    fn test_hidden_folders_are_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            ".github/ci.subsystems.toml",
+            ".subsystems.toml",
+            ".git/hooks.subsystems.toml",
+        ]
+        .iter()
+        {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let mut names: Vec<String> =
+            extract_files_from_repo(dir.path(), "test", ".subsystems.toml", &[], false)
+                .into_iter()
+                .map(|file| file.relative_path)
+                .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![".github/ci.subsystems.toml", ".subsystems.toml"]
+        );
+    }
+}
@@ -1,19 +1,28 @@
 use crate::error::CustomError;
+use crate::git_extraction::keep_worktree;
 use git2::build::RepoBuilder;
 use git2::{
-    AutotagOption, Branch, BranchType, Cred, FetchOptions, Remote, RemoteCallbacks, Repository,
-    ResetType,
+    AutotagOption, Branch, BranchType, Commit, Cred, CredentialType, Direction, ErrorClass,
+    FetchOptions, ProxyOptions, Remote, RemoteCallbacks, Repository, ResetType, Sort,
+    SubmoduleUpdateOptions,
 };
 use log::{debug, info, log_enabled, trace, warn, Level};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
 use std::{env, fs, thread, time};
 
 /// We only want to get the repo up-to-date without re-cloning every time
-/// It deletes the repo folder and re-clones it if it can't open it.
-pub fn open_and_update_or_clone_repo(
+/// It deletes the repo folder and re-clones it if it can't open it, or if it could not be updated
+/// `max_fetch_failures` times in a row (0 to never re-clone it).
+/// The callbacks are built for each transfer, as a re-clone follows a failed update.
+pub fn open_and_update_or_clone_repo<'a>(
     url: &str,
     path: &Path,
-    callbacks: RemoteCallbacks,
+    make_callbacks: impl Fn() -> RemoteCallbacks<'a>,
+    max_fetch_failures: u32,
 ) -> Result<Repository, CustomError> {
     if path.exists() {
         // Try to open the repository then update it
@@ -23,8 +32,30 @@ pub fn open_and_update_or_clone_repo(
         );
         if let Ok(repo) = Repository::open(path) {
             info!("Repository {} opened. Updating it...", path.display());
-            update_repo(&repo, &path, callbacks)?;
-            return Ok(repo);
+            match update_repo(&repo, path, make_callbacks()) {
+                Ok(()) => {
+                    reset_fetch_failures(&repo);
+                    return Ok(repo);
+                }
+                Err(err) => {
+                    // A repository may open but never fetch again, e.g. after a force-push.
+                    // An unreachable server says nothing about the clone: it is not counted
+                    if !is_broken_clone(&err) {
+                        return Err(update_error(err));
+                    }
+                    let err = update_error(err);
+                    let failures = record_fetch_failure(&repo);
+                    if max_fetch_failures == 0 || failures < max_fetch_failures {
+                        return Err(err);
+                    }
+                    warn!(
+                        "{} failed updates in a row for {}, cloning it again. Last error: {}",
+                        failures,
+                        path.display(),
+                        err
+                    );
+                }
+            }
         }
 
         // The path exists and is not valid, this folder must be re-cloned.
@@ -37,19 +68,173 @@ pub fn open_and_update_or_clone_repo(
     let mut builder = RepoBuilder::new();
     let mut fetch_options = FetchOptions::new();
 
-    fetch_options.remote_callbacks(callbacks);
+    fetch_options.remote_callbacks(make_callbacks());
+    fetch_options.proxy_options(provide_proxy_options());
     builder.fetch_options(fetch_options);
 
     match builder.clone(url, path) {
         Ok(repo) => {
             info!("Repository cloned at {}.", path.display());
+
+            // The clone checks out the default branch of the remote: remember it
+            if let Some(head) = repo
+                .head()
+                .ok()
+                .and_then(|h| h.name().map(|n| n.to_owned()))
+            {
+                set_default_branch(&repo, head.as_str(), path);
+            }
             Ok(repo)
         }
-        Err(e) => Err(CustomError::new(format!(
-            "Failed to clone repository: {}",
-            e
-        ))),
+        Err(e) => {
+            // Do not leave a partial clone behind, e.g. when it has been aborted
+            if path.exists() {
+                if let Err(err) = fs::remove_dir_all(path) {
+                    warn!(
+                        "While removing the partial clone {}: {}",
+                        path.display(),
+                        err
+                    );
+                }
+            }
+            Err(CustomError::new(format!(
+                "Failed to clone repository: {}",
+                e
+            )))
+        }
+    }
+}
+
+fn update_error(err: git2::Error) -> CustomError {
+    CustomError::new(format!("Failed to update repository: {}", err))
+}
+
+/// Whether the update failed because of the clone itself, rather than the server or the network
+fn is_broken_clone(err: &git2::Error) -> bool {
+    matches!(
+        err.class(),
+        ErrorClass::Odb
+            | ErrorClass::Index
+            | ErrorClass::Reference
+            | ErrorClass::Object
+            | ErrorClass::Repository
+            | ErrorClass::Zlib
+    )
+}
+
+/// The count of the failed updates in a row is kept in the `.git` folder, to survive restarts
+/// and to be removed with the clone
+const FETCH_FAILURES_FILE: &str = "siostam-fetch-failures";
+
+/// Count one more failed update of the repository, and return the count
+fn record_fetch_failure(repo: &Repository) -> u32 {
+    let file = repo.path().join(FETCH_FAILURES_FILE);
+    let failures = fs::read_to_string(&file)
+        .ok()
+        .and_then(|count| count.trim().parse::<u32>().ok())
+        .unwrap_or(0)
+        + 1;
+    if let Err(err) = fs::write(&file, failures.to_string()) {
+        warn!(
+            "While counting the failed updates in {}: {}",
+            file.display(),
+            err
+        );
+    }
+    failures
+}
+
+/// The repository has been updated: the failures are not in a row anymore
+fn reset_fetch_failures(repo: &Repository) {
+    let file = repo.path().join(FETCH_FAILURES_FILE);
+    if file.exists() {
+        if let Err(err) = fs::remove_file(&file) {
+            warn!(
+                "While resetting the failed updates in {}: {}",
+                file.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Point `origin/HEAD` to the remote branch matching `refs/heads/<name>` of the remote
+fn set_default_branch(repo: &Repository, remote_head: &str, path: &Path) {
+    let target = remote_head.replacen("refs/heads/", "refs/remotes/origin/", 1);
+    if let Err(err) = repo.reference_symbolic(
+        "refs/remotes/origin/HEAD",
+        target.as_str(),
+        true,
+        "siostam: follow the default branch of origin",
+    ) {
+        warn!(
+            "While updating the default branch of {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+/// Create the proxy settings: SIOSTAM_GIT_PROXY if defined, otherwise the proxy is detected
+/// from the git configuration and the HTTPS_PROXY/http_proxy env vars
+pub fn provide_proxy_options() -> ProxyOptions<'static> {
+    let mut proxy_options = ProxyOptions::new();
+    match env::var("SIOSTAM_GIT_PROXY") {
+        Ok(url) => {
+            debug!("Using git proxy {}", url);
+            proxy_options.url(url.as_str());
+        }
+        Err(_) => {
+            proxy_options.auto();
+        }
     }
+    proxy_options
+}
+
+/// Which limit aborted a transfer, see `limit_transfer`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferLimit {
+    Size,
+    Time,
+}
+
+/// Abort the clone/fetch once more than `max_bytes` have been received, or past the `deadline`.
+/// `exceeded` is set to the limit when it happens, to tell this abort from the other failures
+pub fn limit_transfer(
+    callbacks: &mut RemoteCallbacks,
+    max_bytes: Option<u64>,
+    deadline: Option<Instant>,
+    exceeded: Rc<Cell<Option<TransferLimit>>>,
+) {
+    let past_deadline = move || match deadline {
+        Some(deadline) => Instant::now() > deadline,
+        None => false,
+    };
+
+    let transfer_exceeded = exceeded.clone();
+    callbacks.transfer_progress(move |progress| {
+        let too_large = match max_bytes {
+            Some(max_bytes) => progress.received_bytes() as u64 > max_bytes,
+            None => false,
+        };
+        if too_large {
+            transfer_exceeded.set(Some(TransferLimit::Size));
+            return false;
+        }
+        if past_deadline() {
+            transfer_exceeded.set(Some(TransferLimit::Time));
+            return false;
+        }
+        true
+    });
+    // The server may take a while before sending the objects, e.g. while counting them
+    callbacks.sideband_progress(move |_| {
+        if past_deadline() {
+            exceeded.set(Some(TransferLimit::Time));
+            return false;
+        }
+        true
+    });
 }
 
 /// Create an object with the callbacks to handle self_certs and auth
@@ -70,6 +255,7 @@ pub fn provide_callbacks(callbacks: &mut RemoteCallbacks) {
         true
     });
 
+    let credentials = GitCredentials::from_env();
     let mut tries = 0;
 
     // Authenticate by ssh key if they are provided
@@ -100,40 +286,64 @@ pub fn provide_callbacks(callbacks: &mut RemoteCallbacks) {
                      cred.is_username());
         }
 
-        if cred.contains(git2::CredentialType::USERNAME) {
-            git2::Cred::username("git")
-        }
-        else if cred.contains(git2::CredentialType::SSH_KEY) {
-            // TODO Fix SSH authentication. Completely broken at the time
-            let public_key = env::var("SIOSTAM_GIT_SSH_PUBLIC_KEY").ok();
-            let private_key = env::var("SIOSTAM_GIT_SSH_PRIVATE_KEY").expect("private_key is mandatory in this case");
-            let passphrase = env::var("SIOSTAM_GIT_SSH_PASSPHRASE").ok();
+        resolve_credentials(&credentials, cred)
+    });
+}
 
-            // The actual ssh credentials
-            Ok(Cred::ssh_key(
-                "git",
-                public_key
-                        .as_ref()
-                        .map(|x| Path::new(&**x)),
-                Path::new(private_key.as_str()),
-                passphrase.as_ref().map(|x|&**x)
-            ).expect("Could not create credentials object"))
-        }
-        else if cred.contains(git2::CredentialType::USER_PASS_PLAINTEXT){
-            // Transform Option<String> in Option<&str>
-            // Source: https://stackoverflow.com/questions/31233938/converting-from-optionstring-to-optionstr
-            let username = env::var("SIOSTAM_GIT_HTTPS_USERNAME").expect("Username is mandatory in this case");
-            let password = env::var("SIOSTAM_GIT_HTTPS_PASSWORD").expect("Password is mandatory in this case");
+/// The credentials available to authenticate on the git remotes
+#[derive(Clone, Debug, Default)]
+pub struct GitCredentials {
+    pub https_username: Option<String>,
+    pub https_password: Option<String>,
+    pub ssh_public_key: Option<String>,
+    pub ssh_private_key: Option<String>,
+    pub ssh_passphrase: Option<String>,
+}
 
-            Ok(Cred::userpass_plaintext(
-                username.as_str(),
-                    password.as_str()
-                ).expect("Could not create credentials object"))
+impl GitCredentials {
+    /// Read the SIOSTAM_GIT_* env vars
+    pub fn from_env() -> GitCredentials {
+        GitCredentials {
+            https_username: env::var("SIOSTAM_GIT_HTTPS_USERNAME").ok(),
+            https_password: env::var("SIOSTAM_GIT_HTTPS_PASSWORD").ok(),
+            ssh_public_key: env::var("SIOSTAM_GIT_SSH_PUBLIC_KEY").ok(),
+            ssh_private_key: env::var("SIOSTAM_GIT_SSH_PRIVATE_KEY").ok(),
+            ssh_passphrase: env::var("SIOSTAM_GIT_SSH_PASSPHRASE").ok(),
         }
-        else {
-            Err(git2::Error::from_str("Authentication method not supported"))
+    }
+}
+
+/// Choose the credentials to answer what the remote asks for
+/// A missing credential is an error for this remote, instead of a panic
+pub fn resolve_credentials(
+    credentials: &GitCredentials,
+    allowed: CredentialType,
+) -> Result<Cred, git2::Error> {
+    if allowed.contains(CredentialType::USERNAME) {
+        Cred::username("git")
+    } else if allowed.contains(CredentialType::SSH_KEY) {
+        // TODO Fix SSH authentication. Completely broken at the time
+        let private_key = credentials.ssh_private_key.as_ref().ok_or_else(|| {
+            git2::Error::from_str("SIOSTAM_GIT_SSH_PRIVATE_KEY is mandatory for SSH remotes")
+        })?;
+
+        // The actual ssh credentials
+        Cred::ssh_key(
+            "git",
+            credentials.ssh_public_key.as_ref().map(Path::new),
+            Path::new(private_key),
+            credentials.ssh_passphrase.as_deref(),
+        )
+    } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        match (&credentials.https_username, &credentials.https_password) {
+            (Some(username), Some(password)) => Cred::userpass_plaintext(username, password),
+            _ => Err(git2::Error::from_str(
+                "SIOSTAM_GIT_HTTPS_USERNAME and SIOSTAM_GIT_HTTPS_PASSWORD are mandatory for this remote",
+            )),
         }
-    });
+    } else {
+        Err(git2::Error::from_str("Authentication method not supported"))
+    }
 }
 
 /// Fetch data on the `origin` remote for the given repository
@@ -141,7 +351,7 @@ pub fn update_repo(
     repo: &Repository,
     path: &Path,
     callbacks: RemoteCallbacks,
-) -> Result<(), CustomError> {
+) -> Result<(), git2::Error> {
     // Many instructions and comments are from the git2-rs fetch example
     // Source: https://github.com/rust-lang/git2-rs/blob/master/examples/fetch.rs
 
@@ -152,21 +362,31 @@ pub fn update_repo(
     // Create an option to provide callbacks
     let mut fetch_options = FetchOptions::default();
     fetch_options.remote_callbacks(callbacks);
+    fetch_options.proxy_options(provide_proxy_options());
 
     // Woooh, get the updates
     // Maybe TODO display progress to the user
-    remote
-        .download(&[], Some(&mut fetch_options))
-        .map_err(|err| CustomError::new(format!("Error when updating tips: {}", err)))?;
+    remote.download(&[], Some(&mut fetch_options))?;
+
+    // The default branch of the remote may have changed since the clone: follow it
+    let default_branch = remote.list().ok().and_then(|heads| {
+        heads
+            .iter()
+            .find(|h| h.name() == "HEAD")
+            .and_then(|h| h.symref_target())
+            .map(|target| target.to_owned())
+    });
     remote.disconnect();
 
     // Update the references in the remote's namespace to point to the right
     // commits. This may be needed even if there was no packfile to download,
     // which can happen e.g. when the branches have been changed but all the
     // needed objects are available locally.
-    remote
-        .update_tips(None, true, AutotagOption::Unspecified, None)
-        .map_err(|err| CustomError::new(format!("Error when updating tips: {}", err)))?;
+    remote.update_tips(None, true, AutotagOption::Unspecified, None)?;
+
+    if let Some(target) = default_branch {
+        set_default_branch(repo, target.as_str(), path);
+    }
 
     // Display the result to the user
     {
@@ -196,27 +416,151 @@ pub fn update_repo(
     Ok(())
 }
 
+/// Ask the remote for the last commit of the first existing branch, like `git ls-remote` does.
+/// Nothing is downloaded. Returns None if the repository is not cloned yet.
+pub fn find_remote_commit(
+    path: &Path,
+    branch_names: &[String],
+    callbacks: RemoteCallbacks,
+) -> Result<Option<String>, CustomError> {
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(None),
+    };
+    let mut remote: Remote = repo
+        .find_remote("origin")
+        .map_err(|err| CustomError::new(format!("While finding remote origin: {}", err)))?;
+
+    // Only the reference advertisement is needed
+    let connection = remote
+        .connect_auth(
+            Direction::Fetch,
+            Some(callbacks),
+            Some(provide_proxy_options()),
+        )
+        .map_err(|err| CustomError::new(format!("While connecting to origin: {}", err)))?;
+    let heads = connection
+        .list()
+        .map_err(|err| CustomError::new(format!("While listing remote references: {}", err)))?;
+
+    // Without branch, the default branch of the remote is used
+    if branch_names.is_empty() {
+        return Ok(heads
+            .iter()
+            .find(|h| h.name() == "HEAD")
+            .map(|h| h.oid().to_string()));
+    }
+
+    // Same order as in reset_to_branch
+    for branch_name in branch_names {
+        let reference = format!("refs/heads/{}", branch_name);
+        if let Some(head) = heads.iter().find(|h| h.name() == reference) {
+            return Ok(Some(head.oid().to_string()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the default branch of the remote, pointed by `origin/HEAD`
+/// It is set on clone and kept up-to-date by `update_repo`
+pub fn find_default_branch(repo: &Repository, repo_name: &str) -> Result<String, CustomError> {
+    let reference = repo
+        .find_reference("refs/remotes/origin/HEAD")
+        .map_err(|e| {
+            CustomError::new(format!(
+                "No branch given for repo {} and its default branch is unknown: {}",
+                repo_name, e
+            ))
+        })?;
+
+    reference
+        .symbolic_target()
+        .and_then(|target| target.strip_prefix("refs/remotes/origin/"))
+        .map(|name| name.to_owned())
+        .ok_or_else(|| {
+            CustomError::new(format!(
+                "No branch given for repo {} and origin/HEAD is not a branch",
+                repo_name
+            ))
+        })
+}
+
 /// Make sure we are on the wanted branch with no changes whatsoever
+/// The branches are tried in order and the first one existing on the remote is used.
+/// Without branch, the default branch of the remote is used
+/// Returns the id of the commit the repository has been reset to
 pub fn reset_to_branch(
-    branch_name: &str,
+    branch_names: &[String],
     repo: &Repository,
     repo_name: &str,
-) -> Result<(), CustomError> {
+) -> Result<String, CustomError> {
+    let default_branch;
+    let branch_names = if branch_names.is_empty() {
+        default_branch = [find_default_branch(repo, repo_name)?];
+        debug!(
+            "Default branch of repo {} is {}",
+            repo_name, default_branch[0]
+        );
+        &default_branch[..]
+    } else {
+        branch_names
+    };
+
     // We don't want to do any local changes so we can simply use remote branches
     // This allows to find the branch, which is required for the reset thingy
-    let branch_name = format!("origin/{}", branch_name);
-    let branch: Branch = repo
-        .find_branch(branch_name.as_ref(), BranchType::Remote)
-        .map_err(|e| {
-            CustomError::new(format!(
-                "Failed to find branch for repo {}: {}",
-                repo_name, e
-            ))
-        })?;
+    let mut found: Option<(String, Branch)> = None;
+    for branch_name in branch_names {
+        let branch_name = format!("origin/{}", branch_name);
+        match repo.find_branch(branch_name.as_ref(), BranchType::Remote) {
+            Ok(branch) => {
+                found = Some((branch_name, branch));
+                break;
+            }
+            Err(e) => debug!(
+                "Branch {} not found for repo {}: {}",
+                branch_name, repo_name, e
+            ),
+        }
+    }
+    let (branch_name, branch) = found.ok_or_else(|| {
+        CustomError::new(format!(
+            "Failed to find branch for repo {}: none of {:?} exists",
+            repo_name, branch_names
+        ))
+    })?;
+    info!("Using branch {} for repo {}", branch_name, repo_name);
 
     // To do the reset, we need the last commit linked to the branch
     let branch_object = branch.get().peel_to_commit().expect("Commit not found");
 
+    // For debugging, the working tree may be kept as is: only the fetch is done
+    if keep_worktree() {
+        let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| {
+            CustomError::new(format!("Failed to read HEAD of {}: {}", repo_name, e))
+        })?;
+        warn!(
+            "SIOSTAM_GIT_KEEP_WORKTREE is enabled: {} is kept at {} instead of {} ({})",
+            repo_name,
+            head.id(),
+            branch_name,
+            branch_object.id()
+        );
+        return Ok(head.id().to_string());
+    }
+
+    // The reset discards any local edit: make sure nobody is surprised
+    let changes = repo
+        .statuses(None)
+        .map(|statuses| statuses.iter().filter(|s| !s.status().is_ignored()).count())
+        .unwrap_or(0);
+    if changes > 0 {
+        warn!(
+            "{} local change(s) in data/{} are discarded by the reset to {}",
+            changes, repo_name, branch_name
+        );
+    }
+
     // Reset hard to avoid any remaining changes
     repo.reset(branch_object.as_object(), ResetType::Hard, None)
         .map_err(|e| {
@@ -238,6 +582,120 @@ pub fn reset_to_branch(
         branch_object.summary().unwrap_or("no message")
     );
 
+    Ok(branch_object.id().to_string())
+}
+
+/// The last commit which touched a file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LastCommit {
+    pub author_name: String,
+    pub author_email: String,
+    /// Seconds since the Unix epoch
+    pub time: i64,
+}
+
+impl LastCommit {
+    fn from(commit: &Commit) -> LastCommit {
+        let author = commit.author();
+        LastCommit {
+            author_name: author.name().unwrap_or_default().to_owned(),
+            author_email: author.email().unwrap_or_default().to_owned(),
+            time: commit.time().seconds(),
+        }
+    }
+}
+
+/// Find the last commit touching each path (relative to the repository, with `/`) from HEAD.
+/// The history is walked from the newest commit until every path is found. Like `git log`,
+/// a merge only touches a path when it differs from all its parents.
+/// The paths missing from HEAD, e.g. untracked or in a submodule, are missing from the result.
+pub fn find_last_commits(
+    repo: &Repository,
+    paths: &[String],
+) -> Result<HashMap<String, LastCommit>, git2::Error> {
+    let entry_id =
+        |tree: &git2::Tree, path: &str| tree.get_path(Path::new(path)).ok().map(|e| e.id());
+
+    // A path absent from HEAD would never be found: it would only make the walk go to the root
+    let head_tree = repo.head()?.peel_to_tree()?;
+    let mut remaining: HashSet<&str> = paths
+        .iter()
+        .map(|p| p.as_str())
+        .filter(|path| entry_id(&head_tree, path).is_some())
+        .collect();
+    let mut found = HashMap::with_capacity(remaining.len());
+
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(Sort::TIME);
+    for oid in walk {
+        if remaining.is_empty() {
+            break;
+        }
+
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent_trees = commit
+            .parents()
+            .map(|parent| parent.tree())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // A file is touched when its blob differs from the one of every parent
+        let touched: Vec<&str> = remaining
+            .iter()
+            .filter(|path| {
+                let id = entry_id(&tree, path);
+                id.is_some()
+                    && parent_trees
+                        .iter()
+                        .all(|parent_tree| entry_id(parent_tree, path) != id)
+            })
+            .copied()
+            .collect();
+        for path in touched {
+            remaining.remove(path);
+            found.insert(path.to_owned(), LastCommit::from(&commit));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Initialize and update the submodules, recursively, with the same credentials as the repository
+pub fn update_submodules(repo: &Repository, repo_name: &str) -> Result<(), CustomError> {
+    let submodules = repo.submodules().map_err(|e| {
+        CustomError::new(format!("Failed to list submodules of {}: {}", repo_name, e))
+    })?;
+
+    for mut submodule in submodules {
+        let name = submodule.name().unwrap_or("unnamed").to_owned();
+        info!("Updating submodule {} of {}", name, repo_name);
+
+        let mut callbacks = RemoteCallbacks::new();
+        provide_callbacks(&mut callbacks);
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(provide_proxy_options());
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options);
+
+        submodule.update(true, Some(&mut options)).map_err(|e| {
+            CustomError::new(format!(
+                "Failed to update submodule {} of {}: {}",
+                name, repo_name, e
+            ))
+        })?;
+
+        // The submodule may have its own submodules
+        let submodule_repo = submodule.open().map_err(|e| {
+            CustomError::new(format!(
+                "Failed to open submodule {} of {}: {}",
+                name, repo_name, e
+            ))
+        })?;
+        update_submodules(&submodule_repo, &name)?;
+    }
+
     Ok(())
 }
 
@@ -252,3 +710,207 @@ pub fn destroy_repo(path: &Path) {
     debug!("Waiting for OS to recover from this terrible loss.");
     thread::sleep(time::Duration::from_secs(1));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature};
+
+    /// A repository with a single commit on `trunk`, its default branch
+    fn origin_with_trunk(path: &Path) {
+        let repo = Repository::init(path).unwrap();
+        repo.set_head("refs/heads/trunk").unwrap();
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let signature = Signature::now("siostam", "siostam@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reset_to_default_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        let clone = dir.path().join("clone");
+        origin_with_trunk(&origin);
+
+        let repo = open_and_update_or_clone_repo(
+            origin.to_str().unwrap(),
+            &clone,
+            RemoteCallbacks::new,
+            3,
+        )
+        .unwrap();
+        assert_eq!(find_default_branch(&repo, "origin").unwrap(), "trunk");
+        assert!(reset_to_branch(&[], &repo, "origin").is_ok());
+
+        // Updating follows the default branch of the remote as well
+        let repo = open_and_update_or_clone_repo(
+            origin.to_str().unwrap(),
+            &clone,
+            RemoteCallbacks::new,
+            3,
+        )
+        .unwrap();
+        assert_eq!(find_default_branch(&repo, "origin").unwrap(), "trunk");
+    }
+
+    fn https() -> GitCredentials {
+        GitCredentials {
+            https_username: Some("user".to_owned()),
+            https_password: Some("token".to_owned()),
+            ..GitCredentials::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_credentials_username() {
+        let cred = resolve_credentials(&GitCredentials::default(), CredentialType::USERNAME);
+        assert_eq!(cred.unwrap().credtype(), CredentialType::USERNAME.bits());
+    }
+
+    #[test]
+    fn test_resolve_credentials_userpass() {
+        let allowed = CredentialType::USER_PASS_PLAINTEXT;
+        let cred = resolve_credentials(&https(), allowed).unwrap();
+        assert_eq!(cred.credtype(), allowed.bits());
+
+        // A token is given as the password, the username alone is not enough
+        let missing = GitCredentials {
+            https_password: None,
+            ..https()
+        };
+        assert!(resolve_credentials(&missing, allowed).is_err());
+    }
+
+    #[test]
+    fn test_resolve_credentials_ssh() {
+        let allowed = CredentialType::SSH_KEY;
+        assert!(resolve_credentials(&https(), allowed).is_err());
+
+        let ssh = GitCredentials {
+            ssh_private_key: Some("private/id_rsa".to_owned()),
+            ..GitCredentials::default()
+        };
+        let cred = resolve_credentials(&ssh, allowed).unwrap();
+        assert_eq!(cred.credtype(), allowed.bits());
+    }
+
+    #[test]
+    fn test_resolve_credentials_unsupported() {
+        let cred = resolve_credentials(&https(), CredentialType::DEFAULT);
+        assert!(cred.is_err());
+    }
+
+    #[test]
+    fn test_clone_aborted_past_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        let clone = dir.path().join("clone");
+        origin_with_trunk(&origin);
+
+        let exceeded = Rc::new(Cell::new(None));
+        let callbacks = || {
+            let mut callbacks = RemoteCallbacks::new();
+            limit_transfer(&mut callbacks, Some(0), None, exceeded.clone());
+            callbacks
+        };
+        let url = format!("file://{}", origin.display());
+        assert!(open_and_update_or_clone_repo(url.as_str(), &clone, callbacks, 3).is_err());
+        assert_eq!(exceeded.get(), Some(TransferLimit::Size));
+        assert!(!clone.exists());
+
+        // Same with a deadline already past
+        let callbacks = || {
+            let mut callbacks = RemoteCallbacks::new();
+            limit_transfer(&mut callbacks, None, Some(Instant::now()), exceeded.clone());
+            callbacks
+        };
+        assert!(open_and_update_or_clone_repo(url.as_str(), &clone, callbacks, 3).is_err());
+        assert_eq!(exceeded.get(), Some(TransferLimit::Time));
+        assert!(!clone.exists());
+    }
+
+    #[test]
+    fn test_clone_again_after_failed_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        let clone = dir.path().join("clone");
+        origin_with_trunk(&origin);
+        let url = origin.to_str().unwrap();
+
+        // An unreachable remote does not count: the clone itself is fine
+        let repo = open_and_update_or_clone_repo(url, &clone, RemoteCallbacks::new, 2).unwrap();
+        repo.remote_set_url("origin", dir.path().join("missing").to_str().unwrap())
+            .unwrap();
+        assert!(open_and_update_or_clone_repo(url, &clone, RemoteCallbacks::new, 2).is_err());
+        assert!(!repo.path().join(FETCH_FAILURES_FILE).exists());
+        repo.remote_set_url("origin", url).unwrap();
+
+        // The clone opens but can't be fetched anymore
+        fs::write(repo.path().join("refs/remotes/origin/trunk"), "broken").unwrap();
+        drop(repo);
+        assert!(open_and_update_or_clone_repo(url, &clone, RemoteCallbacks::new, 2).is_err());
+        let repo = open_and_update_or_clone_repo(url, &clone, RemoteCallbacks::new, 2).unwrap();
+        assert!(repo.find_reference("refs/remotes/origin/trunk").is_ok());
+        assert!(!repo.path().join(FETCH_FAILURES_FILE).exists());
+    }
+
+    #[test]
+    fn test_find_last_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+
+        // A commit on top of its first parent, HEAD follows the last one
+        let commit = |author: &str, time: i64, parents: &[Oid], files: &[(&str, &str)]| {
+            let mut index = repo.index().unwrap();
+            match parents.first() {
+                Some(parent) => index
+                    .read_tree(&repo.find_commit(*parent).unwrap().tree().unwrap())
+                    .unwrap(),
+                None => index.clear().unwrap(),
+            }
+            for (path, content) in files {
+                let full_path = dir.path().join(path);
+                fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+                fs::write(full_path, content).unwrap();
+                index.add_path(Path::new(path)).unwrap();
+            }
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature =
+                Signature::new(author, "dev@example.com", &git2::Time::new(time, 0)).unwrap();
+            let parents: Vec<Commit> = parents
+                .iter()
+                .map(|parent| repo.find_commit(*parent).unwrap())
+                .collect();
+            let parents: Vec<&Commit> = parents.iter().collect();
+            let oid = repo
+                .commit(None, &signature, &signature, "c", &tree, &parents)
+                .unwrap();
+            repo.set_head_detached(oid).unwrap();
+            oid
+        };
+
+        // Alice adds both files, then Bob only changes b.
+        // Carol changes a on a side branch, that Dave merges
+        let alice = commit("Alice", 1000, &[], &[("a.toml", "a"), ("sub/b.toml", "b")]);
+        let bob = commit("Bob", 2000, &[alice], &[("sub/b.toml", "b2")]);
+        let carol = commit("Carol", 1500, &[alice], &[("a.toml", "a2")]);
+        commit("Dave", 3000, &[bob, carol], &[("a.toml", "a2")]);
+        fs::write(dir.path().join("untracked.toml"), "u").unwrap();
+
+        let paths = vec![
+            "a.toml".to_owned(),
+            "sub/b.toml".to_owned(),
+            "never.toml".to_owned(),
+            "untracked.toml".to_owned(),
+        ];
+        let last_commits = find_last_commits(&repo, &paths).unwrap();
+        assert_eq!(last_commits["a.toml"].author_name, "Carol");
+        assert_eq!(last_commits["sub/b.toml"].author_name, "Bob");
+        assert_eq!(last_commits["sub/b.toml"].time, 2000);
+        assert!(!last_commits.contains_key("never.toml"));
+        assert!(!last_commits.contains_key("untracked.toml"));
+    }
+}
@@ -1,33 +1,287 @@
+use crate::config::{env_flag, CommitFilter};
 use crate::error::CustomError;
+use crate::git_extraction::extraction::SubsystemFile;
 use crate::git_extraction::git::{
-    open_and_update_or_clone_repo, provide_callbacks, reset_to_branch,
+    find_last_commits, find_remote_commit, limit_transfer, open_and_update_or_clone_repo,
+    provide_callbacks, reset_to_branch, update_submodules, TransferLimit,
 };
 use git2::{RemoteCallbacks, Repository};
+use humantime::{format_duration, parse_duration};
+use lazy_static::lazy_static;
+use std::cell::Cell;
 use std::cmp::max;
+use std::collections::HashSet;
+use std::env;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
+pub mod archive;
 pub mod extraction;
 mod git;
 
+/// How many repositories are cloned/updated at the same time by default
+const DEFAULT_FETCH_CONCURRENCY: usize = 4;
+
+/// Read the number of repositories which may be cloned/updated simultaneously
+pub fn get_fetch_concurrency() -> usize {
+    match env::var("SIOSTAM_FETCH_CONCURRENCY") {
+        Ok(value) => match value.parse::<usize>() {
+            Ok(concurrency) if concurrency > 0 => concurrency,
+            _ => {
+                log::error!(
+                    "SIOSTAM_FETCH_CONCURRENCY must be a positive integer, got `{}`",
+                    value
+                );
+                DEFAULT_FETCH_CONCURRENCY
+            }
+        },
+        Err(_) => DEFAULT_FETCH_CONCURRENCY,
+    }
+}
+
+/// How many updates of a repository may fail in a row before it is cloned again, by default
+const DEFAULT_MAX_FETCH_FAILURES: u32 = 3;
+
+/// Read how many updates of a repository may fail in a row before it is cloned again
+/// 0 disables it
+pub fn get_max_fetch_failures() -> u32 {
+    match env::var("SIOSTAM_MAX_FETCH_FAILURES") {
+        Ok(value) => value.parse::<u32>().unwrap_or_else(|_| {
+            log::error!(
+                "SIOSTAM_MAX_FETCH_FAILURES must be a positive integer or 0, got `{}`",
+                value
+            );
+            DEFAULT_MAX_FETCH_FAILURES
+        }),
+        Err(_) => DEFAULT_MAX_FETCH_FAILURES,
+    }
+}
+
+/// Whether the cloned repositories must be left as they are after the fetch,
+/// enabled with `SIOSTAM_GIT_KEEP_WORKTREE=true`. Their extraction is then never cached
+pub fn keep_worktree() -> bool {
+    env_flag("SIOSTAM_GIT_KEEP_WORKTREE")
+}
+
+/// Read how long a clone/update may take, from SIOSTAM_GIT_TIMEOUT. No limit by default
+pub fn get_git_timeout() -> Option<Duration> {
+    let duration = env::var("SIOSTAM_GIT_TIMEOUT").ok()?;
+    match parse_duration(duration.as_str()) {
+        Ok(timeout) => Some(timeout),
+        Err(e) => {
+            log::error!("While parsing SIOSTAM_GIT_TIMEOUT env var: {}", e);
+            None
+        }
+    }
+}
+
+lazy_static! {
+    /// The repositories being cloned/updated in a thread, by name
+    static ref IN_FLIGHT: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// A repository being cloned/updated, until dropped. Only one transfer may use its folder
+struct InFlight(String);
+
+impl InFlight {
+    fn start(name: &str) -> Result<InFlight, CustomError> {
+        let mut in_flight = IN_FLIGHT.lock().map_err(|err| {
+            CustomError::new(format!(
+                "While accessing the transfers in progress: {}",
+                err
+            ))
+        })?;
+        if !in_flight.insert(name.to_owned()) {
+            return Err(CustomError::new(format!(
+                "While fetching {}: the previous transfer, past SIOSTAM_GIT_TIMEOUT, is still running",
+                name
+            )));
+        }
+        Ok(InFlight(name.to_owned()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = IN_FLIGHT.lock() {
+            in_flight.remove(&self.0);
+        }
+    }
+}
+
+/// Clone/update the repository and reset it to the branch
+/// The transfer is aborted past `max_repo_size` megabytes, if set
+/// Returns the path of the repository and the commit it has been reset to
+///
+/// With SIOSTAM_GIT_TIMEOUT, it fails once the timeout is exceeded, e.g. on a hung git server.
+/// The transfer is aborted as soon as git reports progress past the timeout. Until then, it is
+/// left to finish in its own thread, and the repository is not fetched again in the meantime
 pub fn get_git_repo_ready_for_extraction(
-    url: &String,
-    branch: &String,
+    url: &str,
+    branches: &[String],
+    name: &str,
+    recurse_submodules: bool,
+    max_repo_size: Option<u64>,
+) -> Result<(PathBuf, String), CustomError> {
+    let timeout = match get_git_timeout() {
+        Some(timeout) => timeout,
+        None => {
+            return prepare_repo(url, branches, name, recurse_submodules, max_repo_size, None);
+        }
+    };
+
+    let in_flight = InFlight::start(name)?;
+    let deadline = Instant::now() + timeout;
+    let (sender, receiver) = mpsc::channel();
+    let (url, branches, thread_name) = (url.to_owned(), branches.to_vec(), name.to_owned());
+    thread::spawn(move || {
+        let result = prepare_repo(
+            &url,
+            &branches,
+            &thread_name,
+            recurse_submodules,
+            max_repo_size,
+            Some(deadline),
+        );
+        drop(in_flight);
+        // Nobody is waiting anymore if the timeout has been exceeded
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(timeout_error(name, timeout)),
+        Err(RecvTimeoutError::Disconnected) => Err(CustomError::new(format!(
+            "While fetching {}: the transfer stopped unexpectedly",
+            name
+        ))),
+    }
+}
+
+fn timeout_error(name: &str, timeout: Duration) -> CustomError {
+    CustomError::new(format!(
+        "While fetching {}: no result after {} (SIOSTAM_GIT_TIMEOUT)",
+        name,
+        format_duration(timeout)
+    ))
+}
+
+fn prepare_repo(
+    url: &str,
+    branches: &[String],
     name: &str,
-) -> Result<PathBuf, CustomError> {
+    recurse_submodules: bool,
+    max_repo_size: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<(PathBuf, String), CustomError> {
     let path = format!("data/{}", name);
     let path = Path::new(path.as_str());
+    let url = resolve_local_url(url);
 
     // Prepare the repository for extraction
+    let exceeded = Rc::new(Cell::new(None));
+    let make_callbacks = || {
+        let mut callbacks = RemoteCallbacks::new();
+        provide_callbacks(&mut callbacks);
+        limit_transfer(
+            &mut callbacks,
+            max_repo_size.map(|max_repo_size| max_repo_size * 1024 * 1024),
+            deadline,
+            exceeded.clone(),
+        );
+        callbacks
+    };
+    let repo: Repository =
+        open_and_update_or_clone_repo(url.as_str(), path, make_callbacks, get_max_fetch_failures())
+            .map_err(|err| match exceeded.get() {
+                Some(TransferLimit::Size) => CustomError::new(format!(
+                    "{} is larger than max_repo_size ({} MB): transfer aborted",
+                    name,
+                    max_repo_size.unwrap_or_default()
+                )),
+                Some(TransferLimit::Time) => {
+                    timeout_error(name, get_git_timeout().unwrap_or_default())
+                }
+                None => err,
+            })?;
+    let commit = reset_to_branch(branches, &repo, name)?;
+    if recurse_submodules {
+        update_submodules(&repo, name)?;
+    }
+
+    Ok((path.to_path_buf(), commit))
+}
+
+/// Keep only the files whose last commit matches the filter.
+/// The files never committed are dropped as well
+pub fn filter_files_by_last_commit(
+    files: Vec<SubsystemFile>,
+    repo_path: &Path,
+    filter: &CommitFilter,
+) -> Result<Vec<SubsystemFile>, CustomError> {
+    let repo = Repository::open(repo_path).map_err(|err| {
+        CustomError::new(format!(
+            "While opening {} to filter by commit: {}",
+            repo_path.display(),
+            err
+        ))
+    })?;
+    let paths: Vec<String> = files.iter().map(|f| f.relative_path.clone()).collect();
+    let last_commits = find_last_commits(&repo, &paths).map_err(|err| {
+        CustomError::new(format!(
+            "While reading the history of {}: {}",
+            repo_path.display(),
+            err
+        ))
+    })?;
+
+    Ok(files
+        .into_iter()
+        .filter(|file| match last_commits.get(&file.relative_path) {
+            Some(commit) => filter.matches(
+                commit.author_name.as_str(),
+                commit.author_email.as_str(),
+                commit.time,
+            ),
+            None => false,
+        })
+        .collect())
+}
+
+/// A url may be a local repository, e.g. a mirror used for tests. git handles them like remotes,
+/// but a relative path would be resolved from the clone in data/: make it absolute.
+/// Remote urls and file:// urls are kept as is.
+fn resolve_local_url(url: &str) -> String {
+    if url.contains("://") {
+        return url.to_owned();
+    }
+
+    match Path::new(url).canonicalize() {
+        Ok(absolute) => absolute.to_string_lossy().to_string(),
+        // Not a local path, probably a scp-like url such as git@github.com:org/repo.git
+        Err(_) => url.to_owned(),
+    }
+}
+
+/// Get the last commit of the branch on the remote, without fetching the repository
+/// Returns None if the repository has not been cloned yet
+pub fn get_remote_commit(branches: &[String], name: &str) -> Result<Option<String>, CustomError> {
+    let path = format!("data/{}", name);
+
     let mut callbacks = RemoteCallbacks::new();
     provide_callbacks(&mut callbacks);
-    let repo: Repository = open_and_update_or_clone_repo(url.as_str(), path, callbacks)?;
-    reset_to_branch(branch.as_ref(), &repo, &name)?;
-
-    Ok(path.to_path_buf())
+    find_remote_commit(Path::new(path.as_str()), branches, callbacks)
 }
 
 /// Transforms https://github.com/alexcrichton/git2-rs.git into git2-rs
+/// Also works with local repositories such as file:///srv/git/git2-rs/ or ../git2-rs
 pub fn get_name_from_url(url: &str) -> &str {
+    let url = url.trim_end_matches(&['/', '\\'][..]);
     let last_slash = max(url.rfind('\\'), url.rfind('/'))
         .map(|m| m + 1)
         .unwrap_or(0);
@@ -62,6 +316,12 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_name_from_url_local() {
+        assert_eq!(get_name_from_url("file:///srv/git/git2-rs/"), "git2-rs");
+        assert_eq!(get_name_from_url("../mirrors/git2-rs.git"), "git2-rs");
+    }
+
     #[test]
     fn test_get_name_from_url_ssh() {
         assert_eq!(
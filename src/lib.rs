@@ -0,0 +1,21 @@
+//! Map and document systems and subsystems across multiple git repositories.
+//!
+//! The `siostam` binary is built on this library. A program embedding it reads a configuration
+//! with `config::read_config_in_workdir`, then builds the graph with
+//! `subsystem_mapping::Graph::construct_from_config`, or with `construct_from_config_with` to
+//! post-process the graph before it is output.
+
+pub mod config;
+pub mod core;
+pub mod doctor;
+pub mod error;
+mod git_extraction;
+pub mod server;
+pub mod subsystem_mapping;
+pub mod validate;
+mod xml;
+
+pub mod built_info {
+    // The file has been placed there by the build script.
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
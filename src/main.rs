@@ -1,32 +1,30 @@
-use crate::config::{read_config_in_workdir, watch_config, SiostamConfig};
-use crate::core::Core;
-use crate::error::CustomError;
-use crate::server::start_server;
-use crate::subsystem_mapping::dot::generate_file_from_dot;
-use crate::subsystem_mapping::Graph;
 use clap::{App, Arg, SubCommand};
 use dotenv::dotenv;
 use env_logger::Env;
 use humantime::{format_duration, parse_duration};
 use log::{error, info};
+use siostam::built_info;
+use siostam::config::{
+    check_output_name, env_flag, read_config_in_workdir, watch_config, watch_local_targets,
+    SiostamConfig,
+};
+use siostam::core::Core;
+use siostam::doctor::run_doctor;
+use siostam::error::CustomError;
+use siostam::server::start_server;
+use siostam::subsystem_mapping::dot::{generate_file_from_dot, render_svg_from_dot, use_graphviz};
+use siostam::subsystem_mapping::native_svg::render_native_svg;
+use siostam::subsystem_mapping::{check_svg, Graph};
+use siostam::validate::run_validate;
 use std::env;
+use std::fs;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-mod config;
-mod core;
-mod error;
-mod git_extraction;
-mod server;
-mod subsystem_mapping;
-
-pub mod built_info {
-    // The file has been placed there by the build script.
-    include!(concat!(env!("OUT_DIR"), "/built.rs"));
-}
-
 #[actix_rt::main]
 async fn main() {
     // -- CLI setup --
@@ -43,25 +41,114 @@ async fn main() {
                 .takes_value(true)
                 .default_value("Siostam.toml"),
         )
+        .arg(
+            Arg::with_name("env-file")
+                .long("env-file")
+                .value_name("FILE")
+                .help("Loads the environment variables from this file instead of ./.env (overrides SIOSTAM_ENV_FILE)")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("v")
                 .short("v")
                 .multiple(true)
                 .help("Sets the level of verbosity"),
         )
+        .arg(
+            Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .value_name("NAME")
+                .help("Sets the base name of the outputs, e.g. `payments` for data/payments.json (overrides `output_name`)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdout")
+                .long("stdout")
+                .help("Writes the output to stdout instead of data/, for pipes such as `| dot -Tpng`")
+                .conflicts_with("watch"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the output written to stdout, json by default")
+                .possible_values(&["json", "dot", "svg"])
+                .requires("stdout"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("Regenerates the outputs on every change of the configuration or the local folders"),
+        )
         .subcommand(
             SubCommand::with_name("serve")
                 .alias("server")
-                .about("Start as server"),
+                .about("Start as server")
+                .arg(
+                    Arg::with_name("interval")
+                        .short("i")
+                        .long("interval")
+                        .value_name("DURATION")
+                        .help("Sets the interval between updates (overrides SIOSTAM_INTERVAL_BETWEEN_UPDATES)")
+                        .takes_value(true),
+                ),
         )
         .subcommand(
             SubCommand::with_name("init")
-                .about("Add the files in the local directory to get started"),
+                .about("Add the files in a directory to get started")
+                .arg(
+                    Arg::with_name("directory")
+                        .value_name("DIRECTORY")
+                        .help("Sets where the files are added, the current directory by default")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrites the existing files"),
+                )
+                .arg(
+                    Arg::with_name("print")
+                        .long("print")
+                        .help("Writes the example configuration to stdout instead of creating the files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the configuration, graphviz, the data folder and the git credentials"),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Build the graph and check it for duplicate ids, unknown references and cycles")
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Sets how the result is printed, text by default")
+                        .possible_values(&["text", "junit"]),
+                ),
         )
         .get_matches();
 
     // Load .env content into environment variables
-    dotenv().ok();
+    // A file asked for must exist, its error is logged once the logger is ready
+    let env_file = matches
+        .value_of("env-file")
+        .map(|f| f.to_owned())
+        .or_else(|| env::var("SIOSTAM_ENV_FILE").ok());
+    let env_file_error = match env_file.as_ref() {
+        Some(env_file) => dotenv::from_path(env_file)
+            .err()
+            .map(|e| format!("While loading the env file `{}`: {}", env_file, e)),
+        None => {
+            dotenv().ok();
+            None
+        }
+    };
 
     // Initialise the logger with INFO level by default.
     let default_level = match matches.occurrences_of("v") {
@@ -72,9 +159,21 @@ async fn main() {
     let logger_config = Env::default().default_filter_or(default_level);
     env_logger::from_env(logger_config).init();
 
+    if let Some(env_file_error) = env_file_error {
+        error!("{}", env_file_error);
+        std::process::exit(1);
+    }
+
     // Write placeholder files if required to
-    if let Some(_matches) = matches.subcommand_matches("init") {
-        match init() {
+    if let Some(matches) = matches.subcommand_matches("init") {
+        if matches.is_present("print") {
+            if let Err(err) = io::stdout().write_all(include_bytes!("../Siostam.example.toml")) {
+                error!("While writing the example configuration: {}", err);
+            }
+            return;
+        }
+        let directory = Path::new(matches.value_of("directory").unwrap());
+        match init(directory, matches.is_present("force")) {
             Ok(_) => info!("Initialisation complete!"),
             Err(err) => error!("{}", err),
         }
@@ -84,61 +183,141 @@ async fn main() {
     // The config_path has a default value so we can safely unwrap it
     let config_path = matches.value_of("config").unwrap();
 
-    if let Some(_matches) = matches.subcommand_matches("serve") {
-        if let Err(err) = run_server(config_path).await {
+    if matches.subcommand_matches("doctor").is_some() {
+        if !run_doctor(config_path) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        if !run_validate(config_path, matches.value_of("format").unwrap_or("text")) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        if let Err(err) = run_server(config_path, matches.value_of("interval")).await {
             error!("{}", err);
         }
+    } else if matches.is_present("stdout") {
+        // The logs and the errors go to stderr, so stdout only gets the output
+        let format = matches.value_of("format").unwrap_or("json");
+        if let Err(err) = run_mapper_to_stdout(config_path, format) {
+            error!("{}", err);
+            std::process::exit(1);
+        }
     } else {
-        if let Err(err) = run_mapper(config_path) {
+        let name = matches.value_of("name");
+        if let Err(err) = run_mapper(config_path, name) {
             error!("{}", err);
         }
+
+        // Keep going on every change, the errors are only logged to keep watching
+        if matches.is_present("watch") {
+            let result = watch_local_targets(config_path, || {
+                if let Err(err) = run_mapper(config_path, name) {
+                    error!("{}", err);
+                }
+            });
+            if let Err(err) = result {
+                error!("{}", err);
+            }
+        }
     }
 }
 
-fn run_mapper(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn run_mapper(config_path: &str, name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
     // Retrieve the list of all remotes to fetch from the config
     let config: SiostamConfig = read_config_in_workdir(config_path)?;
+    info!("Configuration: {}", config.summary());
+
+    // The CLI flag takes precedence over the configuration, with the same check
+    let name = match name {
+        Some(name) => {
+            check_output_name(name)?;
+            name
+        }
+        None => config.output_name(),
+    };
+    let json_path = format!("data/{}.json", name);
+    let dot_path = format!("data/{}.dot", name);
 
     let graph = Graph::construct_from_config(&config)?;
 
-    graph.output_to_json("data/output.json")?;
+    graph.output_to_json(json_path.as_str())?;
+
+    if config.render().is_svg_disabled() {
+        info!("Finished (the SVG generation is disabled).");
+        return Ok(());
+    }
 
     info!("Proceeding to generate the dot file.");
 
-    graph.output_to_dot("data/output.dot")?;
+    graph.output_to_dot(dot_path.as_str(), config.render())?;
 
     info!("Proceeding to generate the svg file.");
 
-    generate_file_from_dot("data/output.dot");
+    // Same file as graphviz's `-O`: the dot path followed by .svg
+    if !use_graphviz(config.render()) {
+        let svg_path = format!("{}.svg", dot_path);
+        fs::write(svg_path.as_str(), render_native_svg(&graph)).map_err(|err| {
+            CustomError::new(format!("While writing svg file `{}`: {}", svg_path, err))
+        })?;
+    } else {
+        generate_file_from_dot(dot_path.as_str())?;
+    }
 
     info!("Finished.");
     Ok(())
 }
 
-async fn run_server(config_path: &str) -> Result<(), CustomError> {
-    // Update interval
-    let duration = env::var("SIOSTAM_INTERVAL_BETWEEN_UPDATES").unwrap_or_else(|e| {
-        log::error!(
-            "While retrieving SIOSTAM_INTERVAL_BETWEEN_UPDATES env var: {}",
-            e
-        );
-        "5min".to_string()
-    });
-    let interval_between_updates: Duration =
-        parse_duration(duration.as_str()).unwrap_or_else(|e| {
-            log::error!(
-                "While parsing SIOSTAM_INTERVAL_BETWEEN_UPDATES env var: {}",
-                e
-            );
-            Duration::from_secs(5 * 60)
-        });
+fn run_mapper_to_stdout(config_path: &str, format: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config: SiostamConfig = read_config_in_workdir(config_path)?;
+    info!("Configuration: {}", config.summary());
+
+    let graph = Graph::construct_from_config(&config)?;
+
+    let output = match format {
+        "json" => graph.to_json()?,
+        "dot" => graph.to_dot(config.render())?,
+        _ if !use_graphviz(config.render()) => render_native_svg(&graph),
+        _ => {
+            let svg = render_svg_from_dot(graph.to_dot(config.render())?.as_str(), None)?;
+            check_svg(svg.as_str())?;
+            svg
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(output.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+async fn run_server(config_path: &str, interval: Option<&str>) -> Result<(), CustomError> {
+    // Update interval: the CLI flag takes precedence over the env var
+    let interval_between_updates: Duration = match interval {
+        Some(interval) => parse_duration(interval).map_err(|e| {
+            CustomError::new(format!("While parsing --interval `{}`: {}", interval, e))
+        })?,
+        None => read_interval_from_env(),
+    };
     log::info!(
         "Interval between updates: {}",
         format_duration(interval_between_updates).to_string()
     );
 
     // Read the configuration and access a first state of the graph
-    let core = Core::new(config_path, interval_between_updates)?;
+    // In background, the port is opened before the first graph is built
+    let core = if read_background_startup_from_env() {
+        log::info!("The first graph is built in background");
+        Core::new_in_background(config_path, interval_between_updates)?
+    } else {
+        Core::new(config_path, interval_between_updates)?
+    };
     let access_to_core = Arc::new(core);
 
     // Watch for changes of the configuration
@@ -149,22 +328,76 @@ async fn run_server(config_path: &str) -> Result<(), CustomError> {
     Ok(())
 }
 
-fn init() -> Result<(), CustomError> {
-    OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open("Siostam.toml")
-        .map_err(|e| CustomError::new(format!("While creating the Siostam.toml file: {}", e)))?
-        .write_all(include_bytes!("../Siostam.example.toml"))
-        .map_err(|e| CustomError::new(format!("While writing to the Siostam.toml file: {}", e)))?;
-
-    OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(".env")
-        .map_err(|e| CustomError::new(format!("While creating the .env file: {}", e)))?
-        .write_all(include_bytes!("../.env.example"))
-        .map_err(|e| CustomError::new(format!("While writing to the .env file: {}", e)))?;
+/// Read the interval between updates from the env var, with 5 minutes as fallback
+fn read_interval_from_env() -> Duration {
+    let duration = env::var("SIOSTAM_INTERVAL_BETWEEN_UPDATES").unwrap_or_else(|e| {
+        log::error!(
+            "While retrieving SIOSTAM_INTERVAL_BETWEEN_UPDATES env var: {}",
+            e
+        );
+        "5min".to_string()
+    });
+    parse_duration(duration.as_str()).unwrap_or_else(|e| {
+        log::error!(
+            "While parsing SIOSTAM_INTERVAL_BETWEEN_UPDATES env var: {}",
+            e
+        );
+        Duration::from_secs(5 * 60)
+    })
+}
+
+/// Whether the server starts before building the first graph, with SIOSTAM_BACKGROUND_STARTUP
+fn read_background_startup_from_env() -> bool {
+    env_flag("SIOSTAM_BACKGROUND_STARTUP")
+}
+
+fn init(directory: &Path, force: bool) -> Result<(), CustomError> {
+    let files: [(&str, &[u8]); 2] = [
+        ("Siostam.toml", include_bytes!("../Siostam.example.toml")),
+        (".env", include_bytes!("../.env.example")),
+    ];
+
+    // Check everything first, to not leave half of the files behind
+    if !force {
+        for (name, _) in files.iter() {
+            let path = directory.join(name);
+            if path.exists() {
+                return Err(CustomError::new(format!(
+                    "{} already exists, use --force to overwrite it",
+                    path.display()
+                )));
+            }
+        }
+    }
+
+    fs::create_dir_all(directory).map_err(|e| {
+        CustomError::new(format!(
+            "While creating the directory {}: {}",
+            directory.display(),
+            e
+        ))
+    })?;
+
+    for (name, content) in files.iter() {
+        let path = directory.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create_new(!force)
+            .create(force)
+            .truncate(force)
+            .open(&path)
+            .map_err(|e| {
+                CustomError::new(format!("While creating the {} file: {}", path.display(), e))
+            })?
+            .write_all(content)
+            .map_err(|e| {
+                CustomError::new(format!(
+                    "While writing to the {} file: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+    }
 
     Ok(())
 }
@@ -1,6 +1,6 @@
 use crate::core::Core;
 use crate::error::CustomError;
-use crate::server::websocket::PleaseUpdate;
+use crate::server::websocket::{PleaseUpdate, UpdateError};
 use actix::prelude::*;
 use actix::{Actor, Context, Handler, Recipient};
 use std::sync::Arc;
@@ -12,7 +12,7 @@ const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
 /// Subscribe to process signals.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Subscribe(pub Recipient<PleaseUpdate>);
+pub struct Subscribe(pub Recipient<PleaseUpdate>, pub Recipient<UpdateError>);
 
 /// Unsubscribe from process signals.
 #[derive(Message)]
@@ -22,8 +22,10 @@ pub struct Unsubscribe(pub Recipient<PleaseUpdate>);
 /// Actor that provides signal subscriptions
 pub struct UpdateMasterActor {
     last_version: usize,
+    last_config_error: Option<String>,
+    last_build_error: Option<String>,
     core: Arc<Core>,
-    subscribers: Vec<Recipient<PleaseUpdate>>,
+    subscribers: Vec<(Recipient<PleaseUpdate>, Recipient<UpdateError>)>,
 }
 
 impl Actor for UpdateMasterActor {
@@ -43,6 +45,8 @@ impl UpdateMasterActor {
     pub fn new(core: Arc<Core>) -> UpdateMasterActor {
         UpdateMasterActor {
             last_version: 0,
+            last_config_error: None,
+            last_build_error: None,
             subscribers: Vec::new(),
             core,
         }
@@ -59,25 +63,68 @@ impl UpdateMasterActor {
             self.send_please_update_message()
         }
 
+        // Warn the users if the configuration could not be reloaded
+        let config_error = self.core.config_error()?;
+        if config_error != self.last_config_error {
+            if let Some(error) = config_error.as_ref() {
+                self.send_error_message("config-error", error);
+            }
+            self.last_config_error = config_error;
+        }
+
+        // And if the graph could not be updated
+        let build_error = self.core.build_error()?;
+        if build_error != self.last_build_error {
+            if let Some(error) = build_error.as_ref() {
+                self.send_error_message("build-error", error);
+            }
+            self.last_build_error = build_error;
+        }
+
         Ok(())
     }
 
     /// Send signal to all subscribers
     fn send_please_update_message(&mut self) {
-        for subscr in &self.subscribers {
+        for (subscr, _) in &self.subscribers {
             if let Err(err) = subscr.do_send(PleaseUpdate) {
                 log::error!("While sending PleaseUpdate message: {:?}", err);
             }
         }
     }
+
+    /// Send an error to all subscribers
+    fn send_error_message(&mut self, kind: &'static str, error: &str) {
+        for (_, subscr) in &self.subscribers {
+            if let Err(err) = subscr.do_send(UpdateError(kind, error.to_owned())) {
+                log::error!("While sending UpdateError message: {:?}", err);
+            }
+        }
+    }
 }
 
 /// Subscribe to signals
 impl Handler<Subscribe> for UpdateMasterActor {
     type Result = ();
 
+    /// The current errors are sent right away, they may have been broadcast before
     fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) {
-        self.subscribers.push(msg.0);
+        let errors = [
+            ("config-error", self.core.config_error()),
+            ("build-error", self.core.build_error()),
+        ];
+        for (kind, error) in errors.iter() {
+            match error {
+                Ok(Some(error)) => {
+                    if let Err(err) = msg.1.do_send(UpdateError(kind, error.clone())) {
+                        log::error!("While sending UpdateError message: {:?}", err);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => log::error!("{}", err.message),
+            }
+        }
+        self.subscribers.push((msg.0, msg.1));
     }
 }
 
@@ -86,6 +133,6 @@ impl Handler<Unsubscribe> for UpdateMasterActor {
     type Result = ();
 
     fn handle(&mut self, msg: Unsubscribe, _: &mut Self::Context) {
-        self.subscribers.retain(|x| x != &msg.0);
+        self.subscribers.retain(|x| x.0 != msg.0);
     }
 }
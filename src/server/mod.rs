@@ -1,21 +1,33 @@
 use crate::core::Core;
 use crate::error::CustomError;
 use crate::server::actors::UpdateMasterActor;
+use crate::subsystem_mapping::dot::ENGINES;
+use crate::subsystem_mapping::Direction;
 use actix::{Actor, Addr};
 use actix_cors::Cors;
 use actix_files as fs;
+use actix_web::error::BlockingError;
 use actix_web::{http::header, middleware::Logger, web, App, HttpResponse, HttpServer};
-use log::{debug, info};
+use humantime::parse_duration;
+use log::{debug, info, warn};
+use serde_derive::{Deserialize, Serialize};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 mod actors;
+mod streaming;
 mod websocket;
 
-/// We get the executable path and search for the 'public' folder besides it.
+/// SIOSTAM_PUBLIC_DIR takes precedence if defined.
+/// Otherwise, we get the executable path and search for the 'public' folder besides it.
 /// If not available, we search in the working dir
 fn get_public_path() -> String {
+    if let Ok(public_dir) = env::var("SIOSTAM_PUBLIC_DIR") {
+        return public_dir;
+    }
+
     let public_path = match env::current_exe() {
         Ok(mut path) => {
             path.pop();
@@ -33,11 +45,42 @@ fn get_public_path() -> String {
     public_path
 }
 
+/// SIOSTAM_BASE_PATH prefixes every route, to be served under a sub-path by a reverse proxy
+fn get_base_path() -> String {
+    normalize_base_path(env::var("SIOSTAM_BASE_PATH").unwrap_or_default().as_str())
+}
+
+/// `siostam/`, `/siostam` and `/siostam/` all give `/siostam`, while `/` gives no prefix at all
+fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// SIOSTAM_MIN_INTERVAL_BETWEEN_REFRESHES limits how often `/graph/refresh` rebuilds the graph,
+/// with 30 seconds as fallback
+fn get_min_interval_between_refreshes() -> Duration {
+    let duration = match env::var("SIOSTAM_MIN_INTERVAL_BETWEEN_REFRESHES") {
+        Ok(duration) => duration,
+        Err(_) => return Duration::from_secs(30),
+    };
+    parse_duration(duration.as_str()).unwrap_or_else(|e| {
+        log::error!(
+            "While parsing SIOSTAM_MIN_INTERVAL_BETWEEN_REFRESHES env var: {}",
+            e
+        );
+        Duration::from_secs(30)
+    })
+}
+
 pub struct AppState {
     update_master: Arc<Mutex<Addr<UpdateMasterActor>>>,
 }
 
-pub(crate) async fn start_server(access_to_core: Arc<Core>) -> Result<(), CustomError> {
+pub async fn start_server(access_to_core: Arc<Core>) -> Result<(), CustomError> {
     let address = env::var("SIOSTAM_SERVER_SOCKET_ADDRESS").unwrap_or("127.0.0.1".to_owned());
     let port = env::var("SIOSTAM_SERVER_PORT").unwrap_or("4300".to_owned());
     let bind_address = format!("{}:{}", address, port);
@@ -45,11 +88,37 @@ pub(crate) async fn start_server(access_to_core: Arc<Core>) -> Result<(), Custom
     // Detect where to search for static files
     let public_path = get_public_path();
     debug!("Static files will be searched in {}", public_path);
+    let has_public_dir = Path::new(public_path.as_str()).is_dir();
+    if !has_public_dir {
+        warn!(
+            "The public directory `{}` does not exist: a default page is served instead of the front-end. Set SIOSTAM_PUBLIC_DIR to its location",
+            public_path
+        );
+    }
+
+    let base_path = get_base_path();
+    if !base_path.is_empty() {
+        info!("All routes are served under `{}`", base_path);
+    }
+    let landing = landing_html(base_path.as_str());
+    let scope_path = base_path.clone();
+    let min_interval_between_refreshes = get_min_interval_between_refreshes();
 
     HttpServer::new(move || {
         let json_access_to_core = access_to_core.clone();
         let svg_access_to_core = access_to_core.clone();
+        let tree_access_to_core = access_to_core.clone();
+        let systems_access_to_core = access_to_core.clone();
+        let how_tos_access_to_core = access_to_core.clone();
+        let dot_access_to_core = access_to_core.clone();
+        let meta_access_to_core = access_to_core.clone();
+        let health_access_to_core = access_to_core.clone();
+        let ready_access_to_core = access_to_core.clone();
+        let reload_access_to_core = access_to_core.clone();
+        let issues_access_to_core = access_to_core.clone();
+        let refresh_access_to_core = access_to_core.clone();
         let update_master_access_to_core = access_to_core.clone();
+        let landing = landing.clone();
 
         // Wrap an access to the core into app_data to allow the actors from websocket to get updates
         let update_master = actors::UpdateMasterActor::new(update_master_access_to_core).start();
@@ -61,33 +130,133 @@ pub(crate) async fn start_server(access_to_core: Arc<Core>) -> Result<(), Custom
             .app_data(app_data)
             .wrap(Logger::default())
             .service(
-                web::scope("/graph")
-                    .wrap(build_cors().finish())
+                // An empty scope keeps the routes at the root
+                web::scope(scope_path.as_str())
+                    .service(
+                        web::scope("/graph")
+                            .wrap(build_cors().finish())
+                            .route(
+                                "/json",
+                                web::get().to(move |query: web::Query<JsonQuery>| {
+                                    json(json_access_to_core.clone(), query.into_inner())
+                                }),
+                            )
+                            .route(
+                                "/tree",
+                                web::get().to(move || match tree_access_to_core.tree() {
+                                    Ok(tree) => HttpResponse::Ok()
+                                        .content_type(mime::APPLICATION_JSON.as_ref())
+                                        .body(tree),
+                                    Err(err) => HttpResponse::InternalServerError()
+                                        .body(serde_json::to_string(&err).unwrap_or(err.message)),
+                                }),
+                            )
+                            .route(
+                                "/systems",
+                                web::get().to(move || match systems_access_to_core.systems() {
+                                    Ok(systems) => HttpResponse::Ok()
+                                        .content_type(mime::APPLICATION_JSON.as_ref())
+                                        .body(systems),
+                                    Err(err) => HttpResponse::InternalServerError()
+                                        .body(serde_json::to_string(&err).unwrap_or(err.message)),
+                                }),
+                            )
+                            .route(
+                                "/howtos",
+                                web::get().to(move || match how_tos_access_to_core.how_tos() {
+                                    Ok(how_tos) => HttpResponse::Ok()
+                                        .content_type(mime::APPLICATION_JSON.as_ref())
+                                        .body(how_tos),
+                                    Err(err) => HttpResponse::InternalServerError()
+                                        .body(serde_json::to_string(&err).unwrap_or(err.message)),
+                                }),
+                            )
+                            .route(
+                                "/svg",
+                                web::get().to(move |query: web::Query<SvgQuery>| {
+                                    svg(svg_access_to_core.clone(), query.into_inner())
+                                }),
+                            )
+                            .route(
+                                "/dot",
+                                web::get().to(move || {
+                                    if !dot_access_to_core.is_ready() {
+                                        return not_ready();
+                                    }
+                                    match dot_access_to_core.dot() {
+                                        Ok(Some(dot)) => HttpResponse::Ok()
+                                            .content_type("text/vnd.graphviz; charset=utf-8")
+                                            .body(dot),
+                                        Ok(None) => HttpResponse::NotFound().body(
+                                            "The DOT generation is disabled by `disable_svg`",
+                                        ),
+                                        Err(err) => HttpResponse::InternalServerError().body(
+                                            serde_json::to_string(&err).unwrap_or(err.message),
+                                        ),
+                                    }
+                                }),
+                            )
+                            .route(
+                                "/issues",
+                                web::get().to(move || match issues_access_to_core.issues() {
+                                    Ok(issues) => HttpResponse::Ok().json(issues),
+                                    Err(err) => HttpResponse::InternalServerError()
+                                        .body(serde_json::to_string(&err).unwrap_or(err.message)),
+                                }),
+                            )
+                            .route(
+                                "/refresh",
+                                web::post().to(move || {
+                                    refresh(
+                                        refresh_access_to_core.clone(),
+                                        min_interval_between_refreshes,
+                                    )
+                                }),
+                            )
+                            .route(
+                                "/meta",
+                                web::get().to(move || match meta_access_to_core.meta() {
+                                    Ok(meta) => HttpResponse::Ok().json(meta),
+                                    Err(err) => HttpResponse::InternalServerError()
+                                        .body(serde_json::to_string(&err).unwrap_or(err.message)),
+                                }),
+                            ),
+                    )
                     .route(
-                        "/json",
-                        web::get().to(move || match json_access_to_core.json() {
-                            Ok(json) => HttpResponse::Ok().body(json),
+                        "/health",
+                        web::get().to(move || match health_access_to_core.health() {
+                            Ok(health) => HttpResponse::Ok().json(health),
                             Err(err) => HttpResponse::InternalServerError()
                                 .body(serde_json::to_string(&err).unwrap_or(err.message)),
                         }),
                     )
                     .route(
-                        "/svg",
-                        web::get().to(move || match svg_access_to_core.svg() {
-                            Ok(svg) => HttpResponse::Ok()
-                                .content_type(mime::IMAGE_SVG.as_ref())
-                                .body(svg),
-                            Err(err) => HttpResponse::InternalServerError()
-                                .body(serde_json::to_string(&err).unwrap_or(err.message)),
-                        }),
-                    ),
+                        "/config/reload",
+                        web::post().to(move || reload_config(reload_access_to_core.clone())),
+                    )
+                    .route(
+                        "/ready",
+                        web::get().to(move || ready(ready_access_to_core.clone())),
+                    )
+                    .configure(websocket_routes)
+                    .configure(|cfg| {
+                        // Without front-end, explain what is available rather than answering 404
+                        if has_public_dir {
+                            cfg.service(
+                                fs::Files::new("/", public_path.as_str()).index_file("index.html"),
+                            );
+                        } else {
+                            cfg.route("/", web::get().to(move || landing_page(landing.clone())));
+                        }
+                    }),
             )
-            .service(web::scope("/ws").route("/", web::get().to(websocket::index)))
-            .service(fs::Files::new("/", public_path.as_str()).index_file("index.html"))
     })
     .bind(&bind_address)
     .map(|server| {
-        info!("You may access the server at http://localhost:{}/", port);
+        info!(
+            "You may access the server at http://localhost:{}{}/",
+            port, base_path
+        );
         server
     })
     .map_err(|err| {
@@ -103,6 +272,154 @@ pub(crate) async fn start_server(access_to_core: Arc<Core>) -> Result<(), Custom
     Ok(())
 }
 
+/// The new version of the configuration, after a reload on demand
+#[derive(Serialize)]
+struct ConfigReloaded {
+    config_version: usize,
+}
+
+/// Reload the configuration now, where the changes of the file are not noticed
+/// (e.g. some network filesystems or mounted configurations)
+async fn reload_config(core: Arc<Core>) -> HttpResponse {
+    let result = core.reload_config().and_then(|_| core.config_version());
+    match result {
+        Ok(config_version) => HttpResponse::Ok().json(ConfigReloaded { config_version }),
+        Err(err) => HttpResponse::BadRequest()
+            .content_type(mime::APPLICATION_JSON.as_ref())
+            .body(serde_json::to_string(&err).unwrap_or(err.message)),
+    }
+}
+
+/// Readiness probe: 503 until the first graph is built, when started in background
+async fn ready(core: Arc<Core>) -> HttpResponse {
+    if core.is_ready() {
+        HttpResponse::Ok().body("Ready")
+    } else {
+        not_ready()
+    }
+}
+
+/// Until the first graph is built, there is nothing to render yet
+fn not_ready() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().body("The first graph is being built")
+}
+
+/// Query of `/graph/json`
+#[derive(Deserialize)]
+struct JsonQuery {
+    /// Only serve this subsystem and the ones around it
+    focus: Option<String>,
+    /// How many dependencies away from `focus`, 1 by default
+    depth: Option<usize>,
+    /// Which dependencies are followed from `focus`, both by default
+    direction: Option<Direction>,
+}
+
+/// The whole graph, or the part of it around a subsystem
+async fn json(core: Arc<Core>, query: JsonQuery) -> HttpResponse {
+    let result = match query.focus {
+        None => core.shared_graph().map(Some),
+        Some(focus) => core.neighborhood(
+            focus.as_str(),
+            query.depth.unwrap_or(1),
+            query.direction.unwrap_or(Direction::Both),
+        ),
+    };
+
+    match result {
+        Ok(Some(graph)) => HttpResponse::Ok()
+            .content_type(mime::APPLICATION_JSON.as_ref())
+            .streaming(streaming::json_stream(graph)),
+        Ok(None) => HttpResponse::NotFound().body("No subsystem has the `focus` id"),
+        Err(err) => HttpResponse::InternalServerError()
+            .body(serde_json::to_string(&err).unwrap_or(err.message)),
+    }
+}
+
+/// Query of `/graph/svg`
+#[derive(Deserialize)]
+struct SvgQuery {
+    /// A graphviz layout engine to use instead of the configured one, e.g. `dot`
+    engine: Option<String>,
+}
+
+/// The rendered graph, with the configured layout or with the engine asked for
+async fn svg(core: Arc<Core>, query: SvgQuery) -> HttpResponse {
+    if !core.is_ready() {
+        return not_ready();
+    }
+    let result = match query.engine {
+        None => core.svg(),
+        Some(engine) => {
+            if !ENGINES.contains(&engine.as_str()) {
+                return HttpResponse::BadRequest().body(format!(
+                    "Unknown engine `{}`, use one of: {}",
+                    engine,
+                    ENGINES.join(", ")
+                ));
+            }
+            // Graphviz may take a while: do not block the server meanwhile
+            web::block(move || core.svg_with_engine(engine.as_str()))
+                .await
+                .map_err(|err| match err {
+                    BlockingError::Error(err) => err,
+                    BlockingError::Canceled => {
+                        CustomError::new("While rendering the svg: canceled".to_owned())
+                    }
+                })
+        }
+    };
+
+    match result {
+        Ok(Some(svg)) => HttpResponse::Ok()
+            .content_type(mime::IMAGE_SVG.as_ref())
+            .body(svg),
+        Ok(None) => {
+            HttpResponse::NotFound().body("The SVG generation is disabled by `disable_svg`")
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .body(serde_json::to_string(&err).unwrap_or(err.message)),
+    }
+}
+
+/// Rebuild the graph now rather than at the next interval, e.g. from a CI job or a webhook.
+/// Too frequent calls are rejected with 429 to avoid rebuilding the graph in a loop
+async fn refresh(core: Arc<Core>, min_interval: Duration) -> HttpResponse {
+    match Core::request_refresh(core, min_interval) {
+        Ok(None) => HttpResponse::Accepted().body("Graph update requested"),
+        Ok(Some(wait)) => {
+            // Round up so that retrying after this delay is always accepted
+            let seconds = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+            HttpResponse::TooManyRequests()
+                .header(header::RETRY_AFTER, seconds.to_string())
+                .body(format!(
+                    "A refresh has already been requested, retry in {}s",
+                    seconds
+                ))
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .body(serde_json::to_string(&err).unwrap_or(err.message)),
+    }
+}
+
+/// The websocket answers on both `/ws` and `/ws/`, clients use either
+fn websocket_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ws", web::get().to(websocket::index))
+        .route("/ws/", web::get().to(websocket::index));
+}
+
+/// Default page when there is no public directory
+async fn landing_page(html: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(mime::TEXT_HTML_UTF_8.as_ref())
+        .body(html)
+}
+
+/// The links of the default page must follow the base path
+fn landing_html(base_path: &str) -> String {
+    include_str!("landing.html").replace("href=\"/graph/", &format!("href=\"{}/graph/", base_path))
+}
+
 /// Construct the settings for Cross-Origin Resource Sharing (CORS)
 /// Details on https://developer.mozilla.org/fr/docs/Web/HTTP/CORS
 /// We must allow only the given origins to avoid security issues
@@ -127,3 +444,227 @@ fn build_cors() -> Cors {
         .allowed_header(header::CONTENT_TYPE)
         .max_age(3600)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use tempfile::TempDir;
+
+    /// Core over an empty local folder, without graphviz. The folder lives as long as the TempDir
+    fn empty_core() -> (TempDir, Arc<Core>) {
+        let (dir, config_path) = empty_config();
+        let core = Core::new(config_path.to_str().unwrap(), Duration::from_secs(3600)).unwrap();
+        (dir, Arc::new(core))
+    }
+
+    /// A configuration without SVG and with an empty folder as target
+    fn empty_config() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("siostam.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "suffix = \".subsystems.toml\"\n\n[render]\ndisable_svg = true\n\n[[targets]]\nfolder = {:?}\n",
+                dir.path().to_string_lossy()
+            ),
+        )
+        .unwrap();
+        (dir, config_path)
+    }
+
+    #[actix_rt::test]
+    async fn websocket_handshake_on_both_paths() {
+        let (_dir, core) = empty_core();
+        let update_master = UpdateMasterActor::new(core).start();
+        let app_data = web::Data::new(AppState {
+            update_master: Arc::from(Mutex::new(update_master)),
+        });
+        let mut app =
+            test::init_service(App::new().app_data(app_data).configure(websocket_routes)).await;
+
+        for path in ["/ws", "/ws/"].iter() {
+            let req = test::TestRequest::get()
+                .uri(path)
+                .header(header::UPGRADE, "websocket")
+                .header(header::CONNECTION, "upgrade")
+                .header(header::SEC_WEBSOCKET_VERSION, "13")
+                .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS, "{}", path);
+        }
+    }
+
+    #[test]
+    fn base_path_is_normalized() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("siostam"), "/siostam");
+        assert_eq!(normalize_base_path("/siostam/"), "/siostam");
+        assert_eq!(normalize_base_path("/tools/siostam"), "/tools/siostam");
+    }
+
+    #[test]
+    fn landing_page_links_follow_base_path() {
+        let html = landing_html("/siostam");
+        assert!(html.contains("href=\"/siostam/graph/svg\""));
+        assert!(!html.contains("href=\"/graph/"));
+    }
+
+    #[actix_rt::test]
+    async fn refresh_is_rate_limited() {
+        let (_dir, core) = empty_core();
+        let mut app = test::init_service(App::new().route(
+            "/graph/refresh",
+            web::post().to(move || refresh(core.clone(), Duration::from_secs(3600))),
+        ))
+        .await;
+
+        let req = test::TestRequest::post().uri("/graph/refresh").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+        let req = test::TestRequest::post().uri("/graph/refresh").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "3600");
+    }
+
+    #[actix_rt::test]
+    async fn svg_engine_is_validated() {
+        let (_dir, core) = empty_core();
+        let mut app = test::init_service(App::new().route(
+            "/graph/svg",
+            web::get().to(move |query: web::Query<SvgQuery>| svg(core.clone(), query.into_inner())),
+        ))
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/graph/svg?engine=rm")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Known engine, but the SVG generation is disabled in this configuration
+        let req = test::TestRequest::get()
+            .uri("/graph/svg?engine=dot")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn health_describes_the_configuration() {
+        let (_dir, core) = empty_core();
+        let health = serde_json::to_value(core.health().unwrap()).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["config_version"], 0);
+        assert_eq!(health["graph_version"], 0);
+        assert_eq!(health["suffix"], ".subsystems.toml");
+        assert_eq!(health["target_count"], 1);
+    }
+
+    #[actix_rt::test]
+    async fn ready_after_the_first_graph_in_background() {
+        let (_dir, config_path) = empty_config();
+        let core =
+            Core::new_in_background(config_path.to_str().unwrap(), Duration::from_secs(3600))
+                .unwrap();
+        let core = Arc::new(core);
+        let ready_core = core.clone();
+        let svg_core = core.clone();
+        let mut app = test::init_service(
+            App::new()
+                .route("/ready", web::get().to(move || ready(ready_core.clone())))
+                .route(
+                    "/svg",
+                    web::get().to(move |query: web::Query<SvgQuery>| {
+                        svg(svg_core.clone(), query.into_inner())
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/ready").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let req = test::TestRequest::get().uri("/svg").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // The first check builds the graph in another thread
+        Core::check_for_graph_update(core.clone()).unwrap();
+        for _ in 0..100 {
+            if core.is_ready() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let req = test::TestRequest::get().uri("/ready").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // Once built, the configuration decides: `disable_svg` is set here
+        let req = test::TestRequest::get().uri("/svg").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn failed_first_build_is_reported() {
+        let (_dir, config_path) = empty_config();
+        let content = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(&config_path, format!("require_files = true\n{}", content)).unwrap();
+        let config_path = config_path.to_str().unwrap();
+
+        // Without background startup, the server does not start at all
+        match Core::new(config_path, Duration::from_secs(3600)) {
+            Ok(_) => panic!("The first build should fail"),
+            Err(err) => assert!(err.to_string().contains("is the suffix right?")),
+        }
+
+        // In background, it keeps the empty graph and reports the error
+        let core =
+            Arc::new(Core::new_in_background(config_path, Duration::from_secs(3600)).unwrap());
+        Core::check_for_graph_update(core.clone()).unwrap();
+        for _ in 0..100 {
+            if core.build_error().unwrap().is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!core.is_ready());
+        let build_error = core.build_error().unwrap().unwrap();
+        assert!(build_error.contains("is the suffix right?"));
+    }
+
+    #[actix_rt::test]
+    async fn reload_config_on_demand() {
+        let (_dir, config_path) = empty_config();
+        let core = Core::new(config_path.to_str().unwrap(), Duration::from_secs(3600)).unwrap();
+        let core = Arc::new(core);
+        let reload_core = core.clone();
+        let mut app = test::init_service(App::new().route(
+            "/config/reload",
+            web::post().to(move || reload_config(reload_core.clone())),
+        ))
+        .await;
+
+        let content = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(
+            &config_path,
+            content.replace("disable_svg", "flat = true\ndisable_svg"),
+        )
+        .unwrap();
+        let req = test::TestRequest::post().uri("/config/reload").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(core.config_version().unwrap(), 1);
+
+        std::fs::write(&config_path, "suffix = ").unwrap();
+        let req = test::TestRequest::post().uri("/config/reload").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,101 @@
+use actix_web::web;
+use actix_web::web::Bytes;
+use futures::channel::mpsc::{channel, Sender};
+use futures::executor::block_on;
+use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Size of the chunks of a streamed body
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How many chunks may wait for the client. Past it, the serialization waits as well
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Serialize the value as pretty-printed JSON chunk by chunk, for a streaming response.
+/// The serialization runs in the blocking thread pool of actix and only a few chunks are in
+/// memory at once, whatever the size of the value.
+pub fn json_stream<T>(value: Arc<T>) -> impl Stream<Item = Result<Bytes, actix_web::Error>>
+where
+    T: Serialize + Send + Sync + 'static,
+{
+    let (sender, receiver) = channel(CHUNKS_IN_FLIGHT);
+
+    let serialization = web::block(move || {
+        let mut writer = ChunkWriter {
+            sender,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        serde_json::to_writer_pretty(&mut writer, value.as_ref())
+            .map_err(io::Error::from)
+            .and_then(|_| writer.flush())
+    })
+    .map(|result| {
+        // The status is already sent: the client only sees a truncated body
+        if let Err(err) = result {
+            log::warn!("While streaming JSON: {}", err);
+        }
+        None
+    });
+
+    // The pool skips the job once its future is dropped: it is kept in the stream, without item
+    stream::select(
+        receiver.map(Ok),
+        stream::once(serialization).filter_map(future::ready),
+    )
+}
+
+/// Send what is written through the channel, by chunks of CHUNK_SIZE
+struct ChunkWriter {
+    sender: Sender<Bytes>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        // Fails when the client is gone: stop serializing
+        block_on(self.sender.send(chunk))
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_stream_matches_serialization() {
+        let value: Arc<Vec<String>> = Arc::new((0..20_000).map(|i| i.to_string()).collect());
+        let chunks: Vec<Result<Bytes, actix_web::Error>> =
+            block_on(json_stream(value.clone()).collect());
+
+        assert!(chunks.len() > 1);
+        let body: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(body, serde_json::to_vec_pretty(value.as_ref()).unwrap());
+    }
+}
@@ -11,6 +11,7 @@ use crate::server::{websocket, AppState};
 use actix::prelude::*;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use serde_json::json;
 use std::ops::DerefMut;
 use std::sync::{Arc, Mutex};
 
@@ -56,7 +57,10 @@ impl Actor for MyWebSocket {
             match self.update_master.as_ref().lock() {
                 Ok(ref mut handle) => {
                     let actor = handle.deref_mut();
-                    actor.do_send(Subscribe(ctx.address().recipient()));
+                    actor.do_send(Subscribe(
+                        ctx.address().recipient(),
+                        ctx.address().recipient(),
+                    ));
                 }
                 Err(err) => log::error!("{}", err.to_string()),
             }
@@ -147,3 +151,17 @@ impl Handler<PleaseUpdate> for MyWebSocket {
         ctx.text("{ \"message\": \"please-update\" }");
     }
 }
+
+/// Sent when the configuration can't be reloaded (`config-error`) or the graph can't be
+/// updated (`build-error`), with the error
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdateError(pub &'static str, pub String);
+
+impl Handler<UpdateError> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateError, ctx: &mut ws::WebsocketContext<Self>) -> Self::Result {
+        ctx.text(json!({ "message": msg.0, "error": msg.1 }).to_string());
+    }
+}
@@ -0,0 +1,39 @@
+use crate::subsystem_mapping::issues::InvalidFile;
+use crate::subsystem_mapping::SubsystemFileSource;
+use std::collections::HashMap;
+
+/// The parsed files of a git target, at a given commit
+#[derive(Clone, Debug)]
+pub struct CachedTarget {
+    pub commit: String,
+    pub sources: Vec<SubsystemFileSource>,
+    /// The files of this commit which could not be parsed, still reported while it is cached
+    pub invalid_files: Vec<InvalidFile>,
+}
+
+/// Keep the parsed files of each git target between two updates.
+/// If the remote branch did not move, the target does not have to be fetched and parsed again.
+#[derive(Debug, Default)]
+pub struct TargetCache {
+    targets: HashMap<String, CachedTarget>,
+}
+
+impl TargetCache {
+    /// The key identifying a target in the cache
+    pub fn key(url: &str, branches: &[String]) -> String {
+        format!("{}#{}", url, branches.join(","))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&CachedTarget> {
+        self.targets.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, target: CachedTarget) {
+        self.targets.insert(key, target);
+    }
+
+    /// Forget everything, for instance when the configuration changed
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+}
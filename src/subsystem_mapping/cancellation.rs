@@ -0,0 +1,31 @@
+use crate::error::CustomError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Tells a graph construction to stop, e.g. because a newer configuration makes it stale.
+/// The clones share the same flag: the construction checks it between its steps.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails once cancelled, to stop at the next step with `?`
+    pub fn check(&self) -> Result<(), CustomError> {
+        if self.is_cancelled() {
+            Err(CustomError::new(
+                "The graph construction has been cancelled".to_owned(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
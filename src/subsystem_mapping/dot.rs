@@ -1,61 +1,256 @@
+use crate::config::{env_flag, RenderConfig, StatusStyle};
+use crate::error::CustomError;
+use crate::subsystem_mapping::cancellation::Cancellation;
 use handlebars::Handlebars;
-use log::info;
+use lazy_static::lazy_static;
+use log::{info, warn};
 use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The templates of the .dot files: registered name, file in `templates/` and compiled-in content
+const TEMPLATES: [(&str, &str, &str); 8] = [
+    (
+        "tpl_begin_graph",
+        "begin_graph.hbs",
+        include_str!("templates/begin_graph.hbs"),
+    ),
+    (
+        "tpl_end_graph",
+        "end_graph.hbs",
+        include_str!("templates/end_graph.hbs"),
+    ),
+    (
+        "tpl_begin_cluster",
+        "begin_cluster.hbs",
+        include_str!("templates/begin_cluster.hbs"),
+    ),
+    (
+        "tpl_end_cluster",
+        "end_cluster.hbs",
+        include_str!("templates/end_cluster.hbs"),
+    ),
+    ("tpl_node", "node.hbs", include_str!("templates/node.hbs")),
+    ("tpl_edge", "edge.hbs", include_str!("templates/edge.hbs")),
+    (
+        "tpl_cluster_anchor",
+        "cluster_anchor.hbs",
+        include_str!("templates/cluster_anchor.hbs"),
+    ),
+    (
+        "tpl_same_rank",
+        "same_rank.hbs",
+        include_str!("templates/same_rank.hbs"),
+    ),
+];
 
 /// Heavy method which load the handlebars templates requires to generate .dot files
 pub fn init_registry() -> Handlebars {
     let mut reg = Handlebars::new();
+    let from_disk = templates_from_disk();
 
-    reg.register_template_string("tpl_begin_graph", include_str!("templates/begin_graph.hbs"))
-        .expect("Issue when registering tpl_begin_graph");
-    reg.register_template_string("tpl_end_graph", include_str!("templates/end_graph.hbs"))
-        .expect("Issue when registering tpl_end_graph");
+    for (name, file, compiled_in) in TEMPLATES.iter() {
+        if from_disk {
+            let path = format!("{}/{}", TEMPLATES_DIR, file);
+            match fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|template| {
+                    reg.register_template_string(name, template)
+                        .map_err(|err| err.to_string())
+                }) {
+                Ok(()) => continue,
+                Err(err) => warn!(
+                    "While reloading template {}, the compiled-in one is used: {}",
+                    path, err
+                ),
+            }
+        }
+        reg.register_template_string(name, compiled_in)
+            .unwrap_or_else(|err| panic!("Issue when registering {}: {}", name, err));
+    }
 
-    reg.register_template_string(
-        "tpl_begin_cluster",
-        include_str!("templates/begin_cluster.hbs"),
-    )
-    .expect("Issue when registering tpl_begin_cluster");
-    reg.register_template_string("tpl_end_cluster", include_str!("templates/end_cluster.hbs"))
-        .expect("Issue when registering tpl_end_cluster");
+    reg
+}
 
-    reg.register_template_string("tpl_node", include_str!("templates/node.hbs"))
-        .expect("Issue when registering tpl_node");
-    reg.register_template_string("tpl_edge", include_str!("templates/edge.hbs"))
-        .expect("Issue when registering tpl_edge");
+/// Where the templates are read from with `SIOSTAM_TEMPLATES_FROM_DISK`: the checkout built from
+const TEMPLATES_DIR: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/subsystem_mapping/templates"
+);
 
-    reg
+/// Read the templates from the sources on each render instead of the compiled-in ones, to tweak
+/// the DOT output without a rebuild. Debug builds only: enabled with
+/// `SIOSTAM_TEMPLATES_FROM_DISK=true`
+fn templates_from_disk() -> bool {
+    if !cfg!(debug_assertions) {
+        return false;
+    }
+    env_flag("SIOSTAM_TEMPLATES_FROM_DISK")
+}
+
+/// The font used when `[render]` does not set one
+const DEFAULT_FONTNAME: &str = "Helvetica";
+const DEFAULT_FONTSIZE: u32 = 10;
+
+/// The optional attributes of a node
+#[derive(Default)]
+pub struct NodeAttributes<'a> {
+    /// Displayed as a tooltip when hovering the node
+    pub description: Option<&'a str>,
+    /// Makes the node clickable in the SVG
+    pub url: Option<&'a str>,
+    /// Layout hint: the nodes of a group are aligned
+    pub group: Option<&'a str>,
+    /// Type of the subsystem (`db`, `service`...), drawn with the matching shape
+    pub kind: Option<&'a str>,
+    /// Lifecycle of the subsystem (`active`, `deprecated`...), drawn with the matching colors
+    pub status: Option<&'a str>,
 }
 
-/// The DotBuilder store the templates and the handle to the generated file
-pub struct DotBuilder {
+/// The default shapes of the subsystem types, extended/overridden by `[render.shapes]`
+fn default_shapes() -> HashMap<String, String> {
+    [
+        ("db", "cylinder"),
+        ("database", "cylinder"),
+        ("service", "box"),
+        ("queue", "cds"),
+    ]
+    .iter()
+    .map(|(kind, shape)| (kind.to_string(), shape.to_string()))
+    .collect()
+}
+
+/// The default styles of the statuses, extended/overridden by `[render.statuses]`
+/// The nodes and clusters are filled, so the styles keep `filled`
+fn default_statuses() -> HashMap<String, StatusStyle> {
+    let style = |color: Option<&str>, fillcolor: &str, style: &str| StatusStyle {
+        color: color.map(str::to_owned),
+        fillcolor: Some(fillcolor.to_owned()),
+        style: Some(style.to_owned()),
+    };
+    let mut statuses = HashMap::new();
+    statuses.insert("active".to_owned(), style(None, "#c8e6c9", "filled"));
+    statuses.insert("deprecated".to_owned(), style(None, "#ffcdd2", "filled"));
+    statuses.insert(
+        "planned".to_owned(),
+        style(Some("gray40"), "white", "filled,dashed"),
+    );
+    statuses
+}
+
+/// The optional attributes of an edge
+#[derive(Clone, Copy, Default)]
+pub struct EdgeAttributes {
+    /// Pulls the two nodes closer, graphviz's default is 1
+    pub weight: Option<u32>,
+    /// A nice-to-have dependency, drawn dashed and grey
+    pub optional: bool,
+    /// A dependency on its way out, drawn red and crossed at its head
+    pub deprecated: bool,
+}
+
+impl EdgeAttributes {
+    /// The attributes as `key = value` pairs for the template, the values being valid DOT
+    fn properties(self) -> Vec<serde_json::Value> {
+        let mut properties = Vec::new();
+        if let Some(weight) = self.weight {
+            properties.push(json!({"key": "weight", "value": weight.to_string()}));
+        }
+        if self.optional {
+            properties.push(json!({"key": "style", "value": "\"dashed\""}));
+        }
+        // Being deprecated matters more than being optional
+        if self.deprecated {
+            properties.push(json!({"key": "color", "value": "\"red\""}));
+            properties.push(json!({"key": "arrowhead", "value": "\"teenormal\""}));
+        } else if self.optional {
+            properties.push(json!({"key": "color", "value": "\"gray50\""}));
+        }
+        properties
+    }
+}
+
+/// The DotBuilder store the templates and the handle to the generated file (or buffer)
+pub struct DotBuilder<W: Write> {
     reg: Handlebars,
-    bufwriter: BufWriter<File>,
+    shapes: HashMap<String, String>,
+    statuses: HashMap<String, StatusStyle>,
+    /// The escaped attributes shared by every cluster
+    cluster: serde_json::Value,
+    max_label_length: Option<usize>,
+    writer: W,
 }
 
-impl DotBuilder {
-    /// Load handle bars, open-truncate or create the file and print the start of the graph.
-    pub fn new(path: &str) -> io::Result<DotBuilder> {
-        // Prepare the file and the renderer
+impl DotBuilder<BufWriter<File>> {
+    /// Open-truncate or create the file and print the start of the graph in it.
+    pub fn create(path: &str, render: &RenderConfig) -> io::Result<DotBuilder<BufWriter<File>>> {
         let file = File::create(path)?;
+        DotBuilder::new(BufWriter::new(file), render)
+    }
+}
+
+impl<W: Write> DotBuilder<W> {
+    /// Load handle bars and print the start of the graph.
+    pub fn new(mut writer: W, render: &RenderConfig) -> io::Result<DotBuilder<W>> {
+        // Prepare the renderer
         let reg = init_registry();
-        let mut bufwriter = BufWriter::new(file);
 
-        // Write the beginning of the file
-        reg.render_to_write("tpl_begin_graph", &(), &mut bufwriter)
+        // Write the beginning of the file, with the graph-wide options
+        let data = &json!({
+            "rankdir": render.orientation.map(|o| o.as_str()),
+            "fontname": escape_dot_string(render.fontname.as_deref().unwrap_or(DEFAULT_FONTNAME)),
+            "fontsize": render.fontsize.unwrap_or(DEFAULT_FONTSIZE),
+            // Graphviz's default size for the clusters' labels is kept unless asked otherwise
+            "graph_fontsize": render.fontsize,
+        });
+        reg.render_to_write("tpl_begin_graph", data, &mut writer)
             .expect("Error when rendering the beginning of file");
 
-        Ok(DotBuilder { reg, bufwriter })
+        let mut shapes = default_shapes();
+        shapes.extend(render.shapes.clone());
+        let mut statuses = default_statuses();
+        statuses.extend(render.statuses.clone());
+
+        let cluster = json!({
+            "style": render.cluster.style.as_deref().map(escape_dot_string),
+            "bgcolor": render.cluster.bgcolor.as_deref().map(escape_dot_string),
+            "labelloc": render.cluster.labelloc.map(|l| l.as_str()),
+        });
+
+        Ok(DotBuilder {
+            reg,
+            shapes,
+            statuses,
+            cluster,
+            max_label_length: render.max_label_length,
+            writer,
+        })
     }
 
     /// Print a new cluster in the file
-    pub fn begin_cluster(&mut self, indent: &str, id: &str, name: &str) {
-        let data = &json!({"indent": indent, "id": id, "name": name });
+    /// Its SVG group gets the stable id `system_<id>`
+    pub fn begin_cluster(&mut self, indent: &str, id: &str, name: &str, status: Option<&str>) {
+        let id = escape_dot_string(id);
+        let label = self.label(name);
+        let tooltip = label.as_ref().map(|_| escape_dot_string(name));
+        let data = &json!({
+            "indent": indent,
+            "id": id,
+            "name": label.as_deref().unwrap_or(name),
+            "tooltip": tooltip,
+            "cluster": self.cluster,
+            "status": self.status_style(status),
+        });
         self.reg
-            .render_to_write("tpl_begin_cluster", data, &mut self.bufwriter)
+            .render_to_write("tpl_begin_cluster", data, &mut self.writer)
             .expect("Error when rendering the beginning of the cluster");
     }
 
@@ -63,53 +258,464 @@ impl DotBuilder {
     pub fn end_cluster(&mut self, indent: &str) {
         let data = &json!({ "indent": indent });
         self.reg
-            .render_to_write("tpl_end_cluster", data, &mut self.bufwriter)
+            .render_to_write("tpl_end_cluster", data, &mut self.writer)
             .expect("Error when rendering the end of the cluster");
     }
 
+    /// The name cut to `max_label_length` with an ellipsis, if it is longer
+    fn label(&self, name: &str) -> Option<String> {
+        let max = self.max_label_length?;
+        if name.chars().count() <= max {
+            return None;
+        }
+        let mut label: String = name.chars().take(max.saturating_sub(1)).collect();
+        label.push('…');
+        Some(label)
+    }
+
+    /// The escaped style of a status, unknown statuses keep the default style
+    fn status_style(&self, status: Option<&str>) -> Option<StatusStyle> {
+        status
+            .and_then(|status| self.statuses.get(status))
+            .map(|style| StatusStyle {
+                color: style.color.as_deref().map(escape_dot_string),
+                fillcolor: style.fillcolor.as_deref().map(escape_dot_string),
+                style: style.style.as_deref().map(escape_dot_string),
+            })
+    }
+
     /// Print a new node in the file
-    pub fn add_node(&mut self, indent: &str, id: &str, name: &str) {
-        let data = &json!({"indent": indent, "id": id, "name": name });
+    /// Its SVG group gets the stable id `subsystem_<id>`, usable as a URL fragment
+    pub fn add_node(&mut self, indent: &str, id: &str, name: &str, attributes: NodeAttributes) {
+        let id = escape_dot_string(id);
+        // A cut name is given in full in the tooltip, before the description
+        let label = self.label(name);
+        let tooltip = match (label.as_ref(), attributes.description) {
+            (Some(_), Some(description)) => Some(format!("{}\n{}", name, description)),
+            (Some(_), None) => Some(name.to_owned()),
+            (None, description) => description.map(|d| d.to_owned()),
+        };
+        let tooltip = tooltip.as_deref().map(escape_dot_string);
+        let name = label.as_deref().unwrap_or(name);
+        let url = attributes.url.map(escape_dot_string);
+        let group = attributes.group.map(escape_dot_string);
+        // Unknown types keep the default shape
+        let shape = attributes
+            .kind
+            .and_then(|kind| self.shapes.get(kind))
+            .map(|shape| escape_dot_string(shape));
+        let status = self.status_style(attributes.status);
+        let data = &json!({"indent": indent, "id": id, "name": name, "tooltip": tooltip, "url": url, "group": group, "shape": shape, "status": status });
         self.reg
-            .render_to_write("tpl_node", data, &mut self.bufwriter)
+            .render_to_write("tpl_node", data, &mut self.writer)
             .expect("Error when rendering the node");
     }
 
     /// Print a new edge in the file
-    pub fn add_edge(&mut self, indent: &str, id_a: &str, id_b: &str) {
-        let data = &json!({"indent": indent, "idA": id_a, "idB": id_b });
+    /// Its SVG group gets the stable id `<id_a>_to_<id_b>`
+    pub fn add_edge(&mut self, indent: &str, id_a: &str, id_b: &str, attributes: EdgeAttributes) {
+        let (id_a, id_b) = (escape_dot_string(id_a), escape_dot_string(id_b));
+        let properties = attributes.properties();
+        let data = &json!({"indent": indent, "idA": id_a, "idB": id_b, "properties": properties });
+        self.reg
+            .render_to_write("tpl_edge", data, &mut self.writer)
+            .expect("Error when rendering the edge");
+    }
+
+    /// Print the invisible node of a cluster, where the edges to the whole system end.
+    /// Its SVG group gets the stable id `system_<id>_anchor`
+    pub fn add_cluster_anchor(&mut self, indent: &str, cluster: &str) {
+        let data = &json!({"indent": indent, "id": escape_dot_string(cluster), "anchor": cluster_anchor(cluster) });
+        self.reg
+            .render_to_write("tpl_cluster_anchor", data, &mut self.writer)
+            .expect("Error when rendering the anchor of the cluster");
+    }
+
+    /// Print a new edge to the anchor of the cluster of a system (see `add_cluster_anchor`).
+    /// Only the `dot` engine clips it at the border of the cluster (`lhead`), the other engines
+    /// draw it up to the invisible anchor
+    pub fn add_edge_to_cluster(
+        &mut self,
+        indent: &str,
+        id_a: &str,
+        cluster: &str,
+        attributes: EdgeAttributes,
+    ) {
+        let (id_a, id_b) = (escape_dot_string(id_a), cluster_anchor(cluster));
+        let cluster = escape_dot_string(cluster);
+        let properties = attributes.properties();
+        let data = &json!({"indent": indent, "idA": id_a, "idB": id_b, "cluster": cluster, "properties": properties });
         self.reg
-            .render_to_write("tpl_edge", data, &mut self.bufwriter)
+            .render_to_write("tpl_edge", data, &mut self.writer)
             .expect("Error when rendering the edge");
     }
 
-    /// Print the end of the file, flush and close the handle
-    pub fn close(mut self) -> io::Result<()> {
+    /// Print a group of nodes to keep on the same rank
+    pub fn add_same_rank(&mut self, indent: &str, ids: &[&str]) {
+        let ids: Vec<String> = ids.iter().map(|id| escape_dot_string(id)).collect();
+        let data = &json!({"indent": indent, "ids": ids });
         self.reg
-            .render_to_write("tpl_end_graph", &(), &mut self.bufwriter)
+            .render_to_write("tpl_same_rank", data, &mut self.writer)
+            .expect("Error when rendering the rank");
+    }
+
+    /// Print the end of the file, flush and give back the writer
+    pub fn close(mut self) -> io::Result<W> {
+        self.reg
+            .render_to_write("tpl_end_graph", &(), &mut self.writer)
             .expect("Error when rendering the end of file");
-        self.bufwriter.flush()?;
-        Ok(())
+        self.writer.flush()?;
+        Ok(self.writer)
     }
 }
 
-/// Call to graphviz executable to create the SVG file
-pub fn generate_file_from_dot(path: &str) {
-    use std::process::Command;
+/// The escaped id of the anchor node of a cluster. The subsystem ids are not expected to start
+/// with underscores
+fn cluster_anchor(cluster: &str) -> String {
+    format!("__anchor__{}", escape_dot_string(cluster))
+}
+
+/// Escape a text to put it between double quotes in a DOT file
+/// Handlebars escaping is made for HTML and is not enough for multi-line texts
+pub fn escape_dot_string(text: &str) -> String {
+    text.trim()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// The graphviz executable: SIOSTAM_GRAPHVIZ_BIN if defined, otherwise `fdp` from the PATH
+pub fn graphviz_bin() -> String {
+    env::var("SIOSTAM_GRAPHVIZ_BIN").unwrap_or_else(|_| "fdp".to_owned())
+}
+
+lazy_static! {
+    /// Whether the graphviz executable can be started. Checked once, with a warning if not
+    static ref GRAPHVIZ_AVAILABLE: bool = {
+        let available = is_graphviz_available();
+        if !available {
+            warn!(
+                "Graphviz `{}` is not available: the svg is drawn with the built-in layout",
+                graphviz_bin()
+            );
+        }
+        available
+    };
+}
+
+/// Whether the SVG is drawn by graphviz, rather than with the built-in layout because graphviz
+/// is disabled by `disable_graphviz` or can't be started
+pub fn use_graphviz(render: &RenderConfig) -> bool {
+    !render.disable_graphviz && *GRAPHVIZ_AVAILABLE
+}
 
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(&["/C", "fdp", "-Tsvg", path, "-O"])
-            .output()
-            .expect("failed to execute process")
+/// Whether the graphviz executable can be started
+fn is_graphviz_available() -> bool {
+    let bin = graphviz_bin();
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", bin.as_str(), "-V"]);
+        command
     } else {
-        Command::new("fdp")
-            .args(&["-Tsvg", path, "-O"])
-            .output()
-            .expect("failed to execute process")
+        let mut command = Command::new(bin.as_str());
+        command.arg("-V");
+        command
     };
+    command
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Call to graphviz executable to create the SVG file, next to the DOT file with `.svg` added.
+/// Fails with graphviz's errors when it does not succeed, rather than leaving an old SVG file
+pub fn generate_file_from_dot(path: &str) -> Result<(), CustomError> {
+    generate_file_from_dot_cancellable(path, &Cancellation::default())
+}
+
+/// Same as `generate_file_from_dot`, graphviz being killed once `cancellation` is cancelled
+pub fn generate_file_from_dot_cancellable(
+    path: &str,
+    cancellation: &Cancellation,
+) -> Result<(), CustomError> {
+    let bin = graphviz_bin();
+    let mut child = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", bin.as_str(), "-Tsvg", path, "-O"]);
+        command
+    } else {
+        let mut command = Command::new(bin.as_str());
+        command.args(["-Tsvg", path, "-O"]);
+        command
+    }
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|err| CustomError::new(format!("While starting `{}`: {}", bin, err)))?;
+
+    let stdout = read_in_background(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_in_background(child.stderr.take().expect("stderr is piped"));
+    let status = wait_for_graphviz(&mut child, bin.as_str(), cancellation)?;
+    let stdout = stdout.join().expect("The thread reading graphviz panicked");
+    let stderr = stderr.join().expect("The thread reading graphviz panicked");
 
-    String::from_utf8_lossy(output.stdout.as_slice())
+    String::from_utf8_lossy(stdout.as_slice())
         .lines()
         .for_each(|l| info!("{}", l));
+
+    if !status.success() {
+        return Err(CustomError::new(format!(
+            "`{}` failed ({}): {}",
+            bin,
+            status,
+            String::from_utf8_lossy(stderr.as_slice()).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The graphviz layout engines which may be asked for instead of the executable's own
+pub const ENGINES: &[&str] = &[
+    "dot",
+    "neato",
+    "fdp",
+    "sfdp",
+    "circo",
+    "twopi",
+    "osage",
+    "patchwork",
+];
+
+/// Call to graphviz executable with the DOT on stdin and get the SVG from stdout.
+/// The layout is the one of the executable, unless another engine is given (`-K`).
+/// Nothing is written on the disk.
+pub fn render_svg_from_dot(dot: &str, engine: Option<&str>) -> Result<String, CustomError> {
+    render_svg_from_dot_cancellable(dot, engine, &Cancellation::default())
+}
+
+/// Same as `render_svg_from_dot`, graphviz being killed once `cancellation` is cancelled
+pub fn render_svg_from_dot_cancellable(
+    dot: &str,
+    engine: Option<&str>,
+    cancellation: &Cancellation,
+) -> Result<String, CustomError> {
+    let bin = graphviz_bin();
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", bin.as_str(), "-Tsvg"]);
+        command
+    } else {
+        let mut command = Command::new(bin.as_str());
+        command.arg("-Tsvg");
+        command
+    };
+    if let Some(engine) = engine {
+        command.arg(format!("-K{}", engine));
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| CustomError::new(format!("While starting `{}`: {}", bin, err)))?;
+
+    // Feed the DOT and read the outputs from other threads so a large output can't block
+    // the input, and so this one is free to watch for the cancellation
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let dot = dot.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(dot.as_bytes()));
+    let stdout = read_in_background(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_in_background(child.stderr.take().expect("stderr is piped"));
+
+    let status = wait_for_graphviz(&mut child, bin.as_str(), cancellation)?;
+
+    let stdout = stdout.join().expect("The thread reading graphviz panicked");
+    let stderr = stderr.join().expect("The thread reading graphviz panicked");
+    writer
+        .join()
+        .expect("The thread writing to graphviz panicked")
+        .map_err(|err| CustomError::new(format!("While writing DOT to `{}`: {}", bin, err)))?;
+
+    if !status.success() {
+        return Err(CustomError::new(format!(
+            "`{}` failed ({}): {}",
+            bin,
+            status,
+            String::from_utf8_lossy(stderr.as_slice()).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(stdout.as_slice()).to_string())
+}
+
+/// Wait for graphviz to end, killing it once `cancellation` is cancelled
+fn wait_for_graphviz(
+    child: &mut Child,
+    bin: &str,
+    cancellation: &Cancellation,
+) -> Result<ExitStatus, CustomError> {
+    loop {
+        let status = child
+            .try_wait()
+            .map_err(|err| CustomError::new(format!("While running `{}`: {}", bin, err)))?;
+        if let Some(status) = status {
+            return Ok(status);
+        }
+        if cancellation.is_cancelled() {
+            // Killing it closes the pipes, so the threads reading them end as well
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(CustomError::new(format!(
+                "`{}` has been stopped: the graph construction has been cancelled",
+                bin
+            )));
+        }
+        thread::sleep(GRAPHVIZ_POLL_INTERVAL);
+    }
+}
+
+/// How often a running graphviz is checked for its end or a cancellation
+const GRAPHVIZ_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Read everything from a pipe in another thread. A read error gives what was read so far
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_dot_string() {
+        assert_eq!(
+            escape_dot_string("A \"quoted\" text\non two lines\n"),
+            "A \\\"quoted\\\" text\\non two lines"
+        );
+    }
+
+    #[test]
+    fn test_stable_svg_ids() {
+        let mut dot = DotBuilder::new(Vec::new(), &RenderConfig::default()).unwrap();
+        dot.begin_cluster("  ", "back-end", "Back-end", None);
+        dot.add_node("    ", "auth-api", "Auth", NodeAttributes::default());
+        dot.end_cluster("  ");
+        dot.add_edge("  ", "web", "auth-api", EdgeAttributes::default());
+        let attributes = EdgeAttributes {
+            weight: Some(5),
+            optional: true,
+            ..EdgeAttributes::default()
+        };
+        dot.add_edge("  ", "web", "cdn", attributes);
+        let attributes = EdgeAttributes {
+            deprecated: true,
+            ..EdgeAttributes::default()
+        };
+        dot.add_edge("  ", "web", "legacy", attributes);
+        dot.add_same_rank("  ", &["web", "auth-api"]);
+        let dot = String::from_utf8(dot.close().unwrap()).unwrap();
+
+        assert!(dot.contains("subgraph \"cluster__back-end\" {"));
+        assert!(dot.contains("id = \"system_back-end\";"));
+        assert!(dot.contains("\"auth-api\" [\n      id = \"subsystem_auth-api\";"));
+        assert!(dot.contains("\"web\" -> \"auth-api\" [\n    id = \"web_to_auth-api\";"));
+        assert!(dot.contains("{ rank = same; \"web\"; \"auth-api\"; }"));
+        assert!(dot.contains("\"web\" -> \"auth-api\" [\n    id = \"web_to_auth-api\";\n  ]"));
+        assert!(dot.contains(
+            "id = \"web_to_cdn\";\n    weight = 5;\n    style = \"dashed\";\n    color = \"gray50\";\n  ]"
+        ));
+        assert!(dot.contains(
+            "id = \"web_to_legacy\";\n    color = \"red\";\n    arrowhead = \"teenormal\";\n  ]"
+        ));
+    }
+
+    #[test]
+    fn test_cluster_style() {
+        let render: RenderConfig = toml::from_str(
+            "[cluster]\nstyle = \"rounded,filled\"\nbgcolor = \"#eef\"\nlabelloc = \"b\"",
+        )
+        .unwrap();
+        let mut dot = DotBuilder::new(Vec::new(), &render).unwrap();
+        dot.begin_cluster("  ", "back-end", "Back-end", None);
+        dot.end_cluster("  ");
+        let dot = String::from_utf8(dot.close().unwrap()).unwrap();
+
+        assert!(dot.contains("style = \"rounded,filled\";"));
+        assert!(dot.contains("fillcolor = \"#eef\";"));
+        assert!(dot.contains("labelloc = b;"));
+    }
+
+    #[test]
+    fn test_max_label_length() {
+        let render: RenderConfig = toml::from_str("max_label_length = 8").unwrap();
+        let mut dot = DotBuilder::new(Vec::new(), &render).unwrap();
+        let attributes = NodeAttributes {
+            description: Some("Signs the users in"),
+            ..NodeAttributes::default()
+        };
+        dot.add_node("  ", "auth", "Authentication", attributes);
+        dot.add_node("  ", "web", "Web site", NodeAttributes::default());
+        let dot = String::from_utf8(dot.close().unwrap()).unwrap();
+
+        assert!(dot.contains("label = \"Authent…\";"));
+        assert!(dot.contains("tooltip = \"Authentication\\nSigns the users in\";"));
+        assert!(dot.contains("label = \"Web site\";"));
+    }
+
+    #[test]
+    fn test_node_shape_from_type() {
+        let render: RenderConfig = toml::from_str("[shapes]\nqueue = \"box3d\"").unwrap();
+        let mut dot = DotBuilder::new(Vec::new(), &render).unwrap();
+        for (id, kind) in [("a", "db"), ("b", "queue"), ("c", "unknown")].iter() {
+            let attributes = NodeAttributes {
+                kind: Some(kind),
+                ..NodeAttributes::default()
+            };
+            dot.add_node("  ", id, id, attributes);
+        }
+        let dot = String::from_utf8(dot.close().unwrap()).unwrap();
+
+        assert!(dot.contains(
+            "\"a\" [\n    id = \"subsystem_a\";\n    label = \"a\";\n    shape = \"cylinder\";"
+        ));
+        assert!(dot.contains(
+            "\"b\" [\n    id = \"subsystem_b\";\n    label = \"b\";\n    shape = \"box3d\";"
+        ));
+        assert!(dot.contains("\"c\" [\n    id = \"subsystem_c\";\n    label = \"c\";\n  ]"));
+    }
+
+    #[test]
+    fn test_status_style() {
+        let render: RenderConfig =
+            toml::from_str("[statuses.deprecated]\nfillcolor = \"orange\"").unwrap();
+        let mut dot = DotBuilder::new(Vec::new(), &render).unwrap();
+        dot.begin_cluster("  ", "legacy", "Legacy", Some("planned"));
+        for (id, status) in [("a", "active"), ("b", "deprecated"), ("c", "unknown")].iter() {
+            let attributes = NodeAttributes {
+                status: Some(status),
+                ..NodeAttributes::default()
+            };
+            dot.add_node("    ", id, id, attributes);
+        }
+        dot.end_cluster("  ");
+        let dot = String::from_utf8(dot.close().unwrap()).unwrap();
+
+        assert!(dot.contains("style = \"filled,dashed\";\n    color = \"gray40\";"));
+        assert!(dot
+            .contains("label = \"a\";\n      style = \"filled\";\n      fillcolor = \"#c8e6c9\";"));
+        assert!(dot.contains("label = \"b\";\n      fillcolor = \"orange\";"));
+        assert!(dot.contains("label = \"c\";\n    ]"));
+    }
+
+    #[test]
+    fn test_escape_dot_string_backslash() {
+        assert_eq!(escape_dot_string("C:\\data"), "C:\\\\data");
+    }
 }
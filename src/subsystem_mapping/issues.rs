@@ -0,0 +1,505 @@
+use crate::subsystem_mapping::Graph;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Everything that looks wrong in a graph. Nothing here prevents the graph from being rendered,
+/// but the authors probably want to fix it.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct GraphIssues {
+    pub duplicate_ids: Vec<DuplicateId>,
+    /// Ids used by both a system and a subsystem, listing the system files then the subsystem ones
+    pub ids_shared_by_system_and_subsystem: Vec<DuplicateId>,
+    pub unresolved_references: Vec<UnresolvedReference>,
+    /// Each cycle is the list of the subsystem ids depending on each other
+    pub cycles: Vec<Vec<String>>,
+    pub orphan_systems: Vec<OrphanSystem>,
+    /// The files left out of the graph because they could not be read or parsed
+    pub invalid_files: Vec<InvalidFile>,
+}
+
+/// An id used by more than one system (or more than one subsystem)
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DuplicateId {
+    pub kind: &'static str,
+    pub id: String,
+    /// The files declaring it, as `repo_name/path`
+    pub files: Vec<String>,
+}
+
+/// A parent_system or a dependency pointing to an unknown id
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct UnresolvedReference {
+    /// `system` or `subsystem`
+    pub from_kind: &'static str,
+    pub from: String,
+    /// `parent_system` or `dependency`
+    pub reference: &'static str,
+    pub id: String,
+    pub repo_name: String,
+    pub path: String,
+}
+
+impl fmt::Display for UnresolvedReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} `{}` has unknown {} `{}` in {}/{}",
+            self.from_kind, self.from, self.reference, self.id, self.repo_name, self.path
+        )
+    }
+}
+
+/// A system without parent, child systems nor subsystems: often a typo'd id or a stale file
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct OrphanSystem {
+    pub id: String,
+    pub repo_name: String,
+    pub path: String,
+}
+
+impl fmt::Display for OrphanSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "system `{}` has no parent, no child system and no subsystem in {}/{}",
+            self.id, self.repo_name, self.path
+        )
+    }
+}
+
+/// A subsystem file which could not be read or parsed. The rest of the graph is built without it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct InvalidFile {
+    pub repo_name: String,
+    pub path: String,
+    pub error: String,
+}
+
+impl fmt::Display for InvalidFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}: {}", self.repo_name, self.path, self.error)
+    }
+}
+
+impl GraphIssues {
+    /// Search the graph for issues. The links must have been reconstructed before.
+    pub fn from(graph: &Graph) -> GraphIssues {
+        GraphIssues {
+            duplicate_ids: find_duplicate_ids(graph),
+            ids_shared_by_system_and_subsystem: find_ids_shared_by_system_and_subsystem(graph),
+            unresolved_references: find_unresolved_references(graph),
+            cycles: find_cycles(graph),
+            orphan_systems: find_orphan_systems(graph),
+            invalid_files: graph.invalid_files.clone(),
+        }
+    }
+}
+
+/// List the ids declared more than once, for systems then subsystems
+pub fn find_duplicate_ids(graph: &Graph) -> Vec<DuplicateId> {
+    let systems = graph
+        .systems
+        .iter()
+        .map(|s| (s.id.as_str(), format!("{}/{}", s.repo_name, s.path)));
+    let subsystems = graph
+        .subsystems
+        .iter()
+        .map(|s| (s.id.as_str(), format!("{}/{}", s.repo_name, s.path)));
+
+    let mut duplicates = group_duplicates("system", systems);
+    duplicates.append(&mut group_duplicates("subsystem", subsystems));
+    duplicates
+}
+
+/// List the ids used by a system and by a subsystem. The parent_system references lead to the
+/// system while the dependencies lead to the subsystem, which is rarely what the author meant.
+pub fn find_ids_shared_by_system_and_subsystem(graph: &Graph) -> Vec<DuplicateId> {
+    let files = |id: &str| -> (Vec<String>, Vec<String>) {
+        let systems = graph.systems.iter().filter(|s| s.id == id);
+        let subsystems = graph.subsystems.iter().filter(|s| s.id == id);
+        (
+            systems
+                .map(|s| format!("{}/{}", s.repo_name, s.path))
+                .collect(),
+            subsystems
+                .map(|s| format!("{}/{}", s.repo_name, s.path))
+                .collect(),
+        )
+    };
+
+    let mut shared: Vec<DuplicateId> = Vec::new();
+    for system in graph.systems.iter() {
+        // A system declared twice is only reported once here
+        if shared.iter().any(|d| d.id == system.id) {
+            continue;
+        }
+
+        let (mut system_files, mut subsystem_files) = files(system.id.as_str());
+        if !subsystem_files.is_empty() {
+            system_files.append(&mut subsystem_files);
+            shared.push(DuplicateId {
+                kind: "system and subsystem",
+                id: system.id.clone(),
+                files: system_files,
+            });
+        }
+    }
+    shared
+}
+
+/// Group the files by id and keep the ids found more than once, in order of appearance
+fn group_duplicates<'a>(
+    kind: &'static str,
+    items: impl Iterator<Item = (&'a str, String)>,
+) -> Vec<DuplicateId> {
+    let mut order: Vec<&str> = Vec::new();
+    let mut files_by_id: HashMap<&str, Vec<String>> = HashMap::new();
+    for (id, file) in items {
+        let files = files_by_id.entry(id).or_insert_with(|| {
+            order.push(id);
+            Vec::new()
+        });
+        files.push(file);
+    }
+
+    order
+        .into_iter()
+        .filter(|id| files_by_id[id].len() > 1)
+        .map(|id| DuplicateId {
+            kind,
+            id: id.to_owned(),
+            files: files_by_id[id].clone(),
+        })
+        .collect()
+}
+
+/// List the parent systems and dependencies whose id could not be found, with their source file
+pub fn find_unresolved_references(graph: &Graph) -> Vec<UnresolvedReference> {
+    let mut unresolved = Vec::new();
+
+    // 1. Parent systems
+    for system in graph.systems.iter() {
+        if let Some(parent) = system.parent_system.as_ref() {
+            if parent.index().is_none() {
+                unresolved.push(UnresolvedReference {
+                    from_kind: "system",
+                    from: system.id.clone(),
+                    reference: "parent_system",
+                    id: parent.id().to_owned(),
+                    repo_name: system.repo_name.clone(),
+                    path: system.path.clone(),
+                });
+            }
+        }
+    }
+    for subsystem in graph.subsystems.iter() {
+        if let Some(parent) = subsystem.parent_system.as_ref() {
+            if parent.index().is_none() {
+                unresolved.push(UnresolvedReference {
+                    from_kind: "subsystem",
+                    from: subsystem.id.clone(),
+                    reference: "parent_system",
+                    id: parent.id().to_owned(),
+                    repo_name: subsystem.repo_name.clone(),
+                    path: subsystem.path.clone(),
+                });
+            }
+        }
+    }
+
+    // 2. Dependencies
+    for subsystem in graph.subsystems.iter() {
+        for dependency in subsystem.dependencies.iter() {
+            if !dependency.is_resolved() {
+                unresolved.push(UnresolvedReference {
+                    from_kind: "subsystem",
+                    from: subsystem.id.clone(),
+                    reference: "dependency",
+                    id: dependency.subsystem.id().to_owned(),
+                    repo_name: subsystem.repo_name.clone(),
+                    path: subsystem.path.clone(),
+                });
+            }
+        }
+    }
+
+    unresolved
+}
+
+/// List the systems linked to nothing. The links must have been reconstructed before.
+pub fn find_orphan_systems(graph: &Graph) -> Vec<OrphanSystem> {
+    // Mark the systems used as a parent by anything, or depended on as a whole
+    let mut has_children = vec![false; graph.systems.len()];
+    let dependencies = graph
+        .subsystems
+        .iter()
+        .flat_map(|s| s.dependencies.iter())
+        .filter_map(|d| d.system.index());
+    for index in dependencies {
+        has_children[index] = true;
+    }
+    let parents = graph
+        .systems
+        .iter()
+        .filter_map(|s| s.parent_system.as_ref())
+        .chain(
+            graph
+                .subsystems
+                .iter()
+                .filter_map(|s| s.parent_system.as_ref()),
+        );
+    for index in parents.filter_map(|p| p.index()) {
+        has_children[index] = true;
+    }
+
+    graph
+        .systems
+        .iter()
+        .enumerate()
+        .filter(|(index, system)| system.parent_system.is_none() && !has_children[*index])
+        .map(|(_, system)| OrphanSystem {
+            id: system.id.clone(),
+            repo_name: system.repo_name.clone(),
+            path: system.path.clone(),
+        })
+        .collect()
+}
+
+/// Find the groups of subsystems depending on each other, using Tarjan's algorithm
+/// on the strongly connected components of the dependency graph.
+pub fn find_cycles(graph: &Graph) -> Vec<Vec<String>> {
+    // Adjacency list over the subsystem indexes
+    let edges: Vec<Vec<usize>> = graph
+        .subsystems
+        .iter()
+        .map(|s| {
+            s.dependencies
+                .iter()
+                .filter_map(|d| d.subsystem.index())
+                .collect()
+        })
+        .collect();
+
+    // Only the components with more than one node, or a node depending on itself, are cycles
+    strongly_connected_components(&edges)
+        .into_iter()
+        .filter(|c| c.len() > 1 || edges[c[0]].contains(&c[0]))
+        .map(|mut c| {
+            c.sort();
+            c.into_iter()
+                .map(|i| graph.subsystems[i].id.clone())
+                .collect()
+        })
+        .collect()
+}
+
+/// The strongly connected components of a graph given as an adjacency list
+fn strongly_connected_components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut tarjan = Tarjan {
+        edges,
+        index: 0,
+        indexes: vec![None; edges.len()],
+        low_links: vec![0; edges.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; edges.len()],
+        components: Vec::new(),
+    };
+    for node in 0..edges.len() {
+        if tarjan.indexes[node].is_none() {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.components
+}
+
+/// State of the Tarjan's strongly connected components algorithm
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    index: usize,
+    indexes: Vec<Option<usize>>,
+    low_links: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    /// Depth-first walk from `start`. The walk keeps its own stack of the nodes being visited,
+    /// with the position in their dependencies: a long chain of dependencies can't overflow the
+    /// call stack
+    fn visit(&mut self, start: usize) {
+        self.open(start);
+        let mut visiting = vec![(start, 0)];
+        while let Some((node, position)) = visiting.last().copied() {
+            match self.edges[node].get(position) {
+                Some(&next) => {
+                    visiting.last_mut().unwrap().1 += 1;
+                    match self.indexes[next] {
+                        None => {
+                            self.open(next);
+                            visiting.push((next, 0));
+                        }
+                        Some(next_index) if self.on_stack[next] => {
+                            self.low_links[node] = self.low_links[node].min(next_index);
+                        }
+                        Some(_) => {}
+                    }
+                }
+                // All the dependencies are visited: back to the node depending on this one
+                None => {
+                    visiting.pop();
+                    self.close(node);
+                    if let Some(&(parent, _)) = visiting.last() {
+                        self.low_links[parent] = self.low_links[parent].min(self.low_links[node]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn open(&mut self, node: usize) {
+        self.indexes[node] = Some(self.index);
+        self.low_links[node] = self.index;
+        self.index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+    }
+
+    /// The node is the root of a component: pop it
+    fn close(&mut self, node: usize) {
+        if Some(self.low_links[node]) == self.indexes[node] {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem_mapping::test_utils::graph;
+
+    #[test]
+    fn test_find_cycles() {
+        let graph = graph(&[r#"
+            [[subsystem]]
+            id = "a"
+            dependency = [{ id = "b" }]
+
+            [[subsystem]]
+            id = "b"
+            dependency = [{ id = "c" }]
+
+            [[subsystem]]
+            id = "c"
+            dependency = [{ id = "a" }]
+
+            [[subsystem]]
+            id = "d"
+            dependency = [{ id = "a" }, { id = "d" }]
+            "#]);
+
+        assert_eq!(find_cycles(&graph), vec![vec!["a", "b", "c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn test_long_dependency_chain() {
+        // A chain deep enough to overflow the call stack with a recursive walk, closed by a cycle
+        let count = 200_000;
+        let mut edges: Vec<Vec<usize>> = (0..count).map(|i| vec![i + 1]).collect();
+        edges[count - 1] = vec![count - 2];
+
+        let components = strongly_connected_components(&edges);
+        assert_eq!(components.len(), count - 1);
+        assert!(components.contains(&vec![count - 1, count - 2]));
+    }
+
+    #[test]
+    fn test_find_duplicate_ids() {
+        let graph = graph(&[
+            "[[subsystem]]\nid = \"a\"",
+            "[[subsystem]]\nid = \"a\"\n[[subsystem]]\nid = \"b\"",
+        ]);
+
+        assert_eq!(
+            find_duplicate_ids(&graph),
+            vec![DuplicateId {
+                kind: "subsystem",
+                id: "a".to_owned(),
+                files: vec![
+                    "test/0.subsystems.toml".to_owned(),
+                    "test/1.subsystems.toml".to_owned()
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_ids_shared_by_system_and_subsystem() {
+        let graph = graph(&[
+            "[system]\nid = \"auth\"\n[[subsystem]]\nid = \"login\"",
+            "[[subsystem]]\nid = \"auth\"",
+        ]);
+
+        assert_eq!(
+            find_ids_shared_by_system_and_subsystem(&graph),
+            vec![DuplicateId {
+                kind: "system and subsystem",
+                id: "auth".to_owned(),
+                files: vec![
+                    "test/0.subsystems.toml".to_owned(),
+                    "test/1.subsystems.toml".to_owned()
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_orphan_systems() {
+        let graph = graph(&[
+            "[system]\nid = \"parent\"",
+            "stored_in_system = \"parent\"\n[system]\nid = \"child\"\n[[subsystem]]\nid = \"a\"",
+            "[system]\nid = \"alone\"",
+        ]);
+
+        let orphans: Vec<String> = find_orphan_systems(&graph)
+            .iter()
+            .map(|o| o.to_string())
+            .collect();
+        assert_eq!(
+            orphans,
+            vec!["system `alone` has no parent, no child system and no subsystem in test/2.subsystems.toml"]
+        );
+    }
+
+    #[test]
+    fn test_find_unresolved_references() {
+        let graph = graph(&[r#"
+            stored_in_system = "nowhere"
+
+            [[subsystem]]
+            id = "a"
+            dependency = [{ id = "missing" }]
+            "#]);
+
+        let unresolved: Vec<String> = find_unresolved_references(&graph)
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            unresolved,
+            vec![
+                "subsystem `a` has unknown parent_system `nowhere` in test/0.subsystems.toml",
+                "subsystem `a` has unknown dependency `missing` in test/0.subsystems.toml",
+            ]
+        );
+    }
+}
@@ -1,26 +1,58 @@
 use crate::built_info;
-use crate::config::SiostamConfig;
+use crate::config::{env_flag, RenderConfig, SiostamConfig, Target};
 use crate::error::CustomError;
-use crate::git_extraction::extraction::{extract_files_from_repo, SubsystemFile};
-use crate::git_extraction::{get_git_repo_ready_for_extraction, get_name_from_url};
-use crate::subsystem_mapping::dot::{generate_file_from_dot, DotBuilder};
+use crate::git_extraction::archive::unpack_archive;
+use crate::git_extraction::extraction::{
+    expand_folder_glob, extract_files_from_repo, is_glob, SubsystemFile,
+};
+use crate::git_extraction::{
+    filter_files_by_last_commit, get_fetch_concurrency, get_git_repo_ready_for_extraction,
+    get_name_from_url, get_remote_commit, keep_worktree,
+};
+use crate::subsystem_mapping::cache::{CachedTarget, TargetCache};
+use crate::subsystem_mapping::cancellation::Cancellation;
+use crate::subsystem_mapping::dot::{
+    generate_file_from_dot_cancellable, render_svg_from_dot, render_svg_from_dot_cancellable,
+    use_graphviz, DotBuilder, EdgeAttributes, NodeAttributes,
+};
+use crate::subsystem_mapping::issues::{
+    find_ids_shared_by_system_and_subsystem, find_orphan_systems, find_unresolved_references,
+    GraphIssues, InvalidFile,
+};
+use crate::subsystem_mapping::native_svg::render_native_svg;
 use crate::subsystem_mapping::references::ReferenceByIndex;
+use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
+use rayon::prelude::*;
 use serde_derive::{Deserialize, Serialize};
-use std::borrow::BorrowMut;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::{fs, io};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use std::{env, fs, io, iter};
 
 // Structure used to avoid refcount
 mod references;
+// Parsed files kept between updates
+pub mod cache;
+// Stop a construction made stale by a newer configuration
+pub mod cancellation;
+// Diagnostics on the graph content
+pub mod issues;
+#[cfg(test)]
+mod test_utils;
 // Output in dot format
 pub mod dot;
+// SVG output without graphviz
+pub mod native_svg;
 
 // -- Models in source files --
 // The models stored in files
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SubsystemFileSource {
     stored_in_system: Option<String>,
     system: Option<SystemSource>,
@@ -32,24 +64,48 @@ pub struct SubsystemFileSource {
     // It is stored as Option because it is added by code, but we can unwrap it safely
     repo_name: Option<String>,
     path: Option<String>,
+    // The folder containing the file, used to resolve the relative paths inside it
+    #[serde(skip)]
+    directory: Option<PathBuf>,
+    // The folder walked for this file: the description files must not be read outside of it
+    #[serde(skip)]
+    root: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SystemSource {
     id: Option<String>,
     name: Option<String>,
+    // What the system is (`db`, `service`...), `type` in the files
+    #[serde(rename = "type")]
+    kind: Option<String>,
     description: Option<String>,
+    // Path to a file containing the description, relative to the subsystem file
+    description_file: Option<String>,
+    // Lifecycle (`active`, `deprecated`, `planned`...), drawn with the matching colors
+    status: Option<String>,
 
     // Stored as both how_to and howto to handle both naming-conventions
     howto: Option<Vec<HowToSource>>,
     how_to: Option<Vec<HowToSource>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SubsystemSource {
     id: Option<String>,
     name: Option<String>,
+    // What the subsystem is (`db`, `service`...), `type` in the files
+    #[serde(rename = "type")]
+    kind: Option<String>,
     description: Option<String>,
+    // Path to a file containing the description, relative to the subsystem file
+    description_file: Option<String>,
+    // Lifecycle (`active`, `deprecated`, `planned`...), drawn with the matching colors
+    status: Option<String>,
+
+    // Layout hints: subsystems with the same group are aligned, with the same rank side by side
+    group: Option<String>,
+    rank: Option<String>,
 
     // Stored as both dependency and dependencies to handle both naming-conventions
     dependency: Option<Vec<SubsystemDependencySource>>,
@@ -59,16 +115,54 @@ pub struct SubsystemSource {
     how_to: Option<Vec<HowToSource>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct HowToSource {
     url: Option<String>,
     text: Option<String>,
+    // Kind of link (`runbook`, `dashboard`, `repo`, `oncall`...) for the front-end to group them
+    category: Option<String>,
+    // Deep link in the page: an anchor (`#setup`) or a line (`#L42`), replacing the url's own
+    anchor: Option<String>,
+    line: Option<u32>,
+}
+
+impl HowToSource {
+    /// The link, if it has an url: the anchor, or else the line, becomes its fragment
+    fn to_how_to(&self) -> Option<HowTo> {
+        let url = self.url.as_ref()?;
+        let fragment = match (self.anchor.as_ref(), self.line) {
+            (Some(anchor), _) => Some(anchor.trim_start_matches('#').to_owned()),
+            (None, Some(line)) => Some(format!("L{}", line)),
+            (None, None) => None,
+        };
+        let url = match fragment {
+            Some(fragment) => {
+                let without_fragment = url.split('#').next().unwrap_or(url);
+                format!("{}#{}", without_fragment, fragment)
+            }
+            None => url.clone(),
+        };
+
+        Some(HowTo {
+            text: self.text.clone().unwrap_or_else(|| url.clone()),
+            url,
+            category: self.category.clone(),
+        })
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SubsystemDependencySource {
     id: Option<String>,
     why: Option<String>,
+    // Layout hint: a heavier dependency is drawn shorter
+    weight: Option<u32>,
+    // A nice-to-have dependency (e.g. behind a feature flag), drawn dashed
+    #[serde(default)]
+    optional: bool,
+    // Lifecycle dates (e.g. `2020-03-01`), kept as written. A deprecated dependency is drawn red
+    since: Option<String>,
+    deprecated: Option<String>,
 }
 
 // -- Transformation --
@@ -89,49 +183,81 @@ fn iterate_over_option_vecs<'a, T>(
 }
 
 impl SubsystemFileSource {
+    /// Use the content of the description file if there is one, the inline description otherwise.
+    /// The repositories are not trusted: the description file must be a relative path which stays
+    /// inside the folder walked, symbolic links included, since its content is served
+    fn read_description(
+        &self,
+        description: &Option<String>,
+        description_file: &Option<String>,
+    ) -> Result<Option<String>, CustomError> {
+        let description_file = match description_file {
+            Some(description_file) => description_file,
+            None => return Ok(description.clone()),
+        };
+        let error = |reason: &dyn std::fmt::Display| {
+            CustomError::new(format!(
+                "While reading description file `{}` referenced in {}/{}: {}",
+                description_file,
+                self.repo_name.clone().unwrap_or_default(),
+                self.path.clone().unwrap_or_default(),
+                reason
+            ))
+        };
+        let outside = || error(&"it must be a relative path inside the repository");
+
+        // Checked before touching the filesystem, not to tell which files exist outside
+        let is_relative = Path::new(description_file)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        let (directory, root) = match (self.directory.as_ref(), self.root.as_ref()) {
+            (Some(directory), Some(root)) if is_relative => (directory, root),
+            _ => return Err(outside()),
+        };
+
+        let path = fs::canonicalize(directory.join(description_file)).map_err(|err| error(&err))?;
+        let root = fs::canonicalize(root).map_err(|err| error(&err))?;
+        if !path.starts_with(&root) {
+            return Err(outside());
+        }
+        let content = fs::read_to_string(&path).map_err(|err| error(&err))?;
+        Ok(Some(content))
+    }
+
     /// Get a fully checked system out of the file
     /// If invalid, None is returned
-    pub fn extract_system(&self) -> Option<System> {
+    pub fn extract_system(&self) -> Result<Option<System>, CustomError> {
         // This case is pretty obvious, don't you think
         if self.system.is_none() {
-            return None;
+            return Ok(None);
         }
 
         // If we don't have neither name nor id, it can't be valid either
         let system = self.system.as_ref().unwrap();
         if system.id.is_none() && system.name.is_none() {
-            return None;
+            return Ok(None);
         }
 
         // Process the related how-to
-        let mut how_to_vec = Vec::new();
-        for how_to in iterate_over_option_vecs(&system.how_to, &system.howto) {
-            if how_to.url.is_some() {
-                how_to_vec.push(HowTo {
-                    url: how_to.url.as_ref().unwrap().clone(),
-                    text: how_to
-                        .text
-                        .as_ref()
-                        .or(how_to.url.as_ref())
-                        .unwrap()
-                        .clone(),
-                })
-            }
-        }
+        let how_to_vec = iterate_over_option_vecs(&system.how_to, &system.howto)
+            .filter_map(HowToSource::to_how_to)
+            .collect();
 
-        Some(System {
+        Ok(Some(System {
             // If there is no id, use the name as backup
             id: system.id.as_ref().or(system.name.as_ref()).unwrap().clone(),
 
             // If there is no name, use the id as backup
             name: system.name.as_ref().or(system.id.as_ref()).unwrap().clone(),
+            kind: system.kind.clone(),
+            status: system.status.clone(),
 
             // Store the repo_name/path to display it on the front-end
             repo_name: self.repo_name.clone().unwrap(),
             path: self.path.clone().unwrap(),
 
             // Simple metadata
-            description: system.description.clone(),
+            description: self.read_description(&system.description, &system.description_file)?,
 
             // If specified, the system will be added to the parent system
             // This will be done later because all files must be extracted before
@@ -141,12 +267,15 @@ impl SubsystemFileSource {
                 .map(|s| ReferenceByIndex::new(s)),
 
             how_to: how_to_vec,
-        })
+        }))
     }
 
     /// Get a valid subsystems from a file
     /// Invalid subsystems are ignored
-    pub fn extract_subsystems(&self, parent_system: Option<&String>) -> Vec<Subsystem> {
+    pub fn extract_subsystems(
+        &self,
+        parent_system: Option<&String>,
+    ) -> Result<Vec<Subsystem>, CustomError> {
         let mut subsystems = Vec::new();
 
         // Iterate over both subsystem and subsystems to handle both naming-conventions
@@ -161,29 +290,23 @@ impl SubsystemFileSource {
             for dependency in
                 iterate_over_option_vecs(&subsystem.dependencies, &subsystem.dependency)
             {
-                if dependency.id.is_some() {
+                if let Some(id) = dependency.id.as_ref() {
                     dependencies.push(SubsystemDependency {
-                        subsystem: ReferenceByIndex::new(dependency.id.as_ref().unwrap()),
+                        subsystem: ReferenceByIndex::new(id),
+                        system: ReferenceByIndex::new(id),
                         why: dependency.why.clone(),
+                        weight: dependency.weight,
+                        optional: dependency.optional,
+                        since: dependency.since.clone(),
+                        deprecated: dependency.deprecated.clone(),
                     })
                 }
             }
 
             // Process the related how-to
-            let mut how_to_vec = Vec::new();
-            for how_to in iterate_over_option_vecs(&subsystem.how_to, &subsystem.howto) {
-                if how_to.url.is_some() {
-                    how_to_vec.push(HowTo {
-                        url: how_to.url.as_ref().unwrap().clone(),
-                        text: how_to
-                            .text
-                            .as_ref()
-                            .or(how_to.url.as_ref())
-                            .unwrap()
-                            .clone(),
-                    })
-                }
-            }
+            let how_to_vec = iterate_over_option_vecs(&subsystem.how_to, &subsystem.howto)
+                .filter_map(HowToSource::to_how_to)
+                .collect();
 
             subsystems.push(Subsystem {
                 // If there is no id, use the name as backup
@@ -207,7 +330,8 @@ impl SubsystemFileSource {
                 path: self.path.clone().unwrap(),
 
                 // Simple metadata
-                description: subsystem.description.clone(),
+                description: self
+                    .read_description(&subsystem.description, &subsystem.description_file)?,
 
                 // If specified, the system will be added to the parent system
                 // The parent system is decided before this method is call
@@ -217,20 +341,32 @@ impl SubsystemFileSource {
                 // The previously computed dependencies
                 dependencies,
                 how_to: how_to_vec,
+
+                kind: subsystem.kind.clone(),
+                status: subsystem.status.clone(),
+                group: subsystem.group.clone(),
+                rank: subsystem.rank.clone(),
+
+                // Computed once the links are reconstructed
+                in_degree: 0,
+                out_degree: 0,
             });
         }
 
-        subsystems
+        Ok(subsystems)
     }
 }
 
 // -- Post-processed models --
 // The models transformed for usage in graphs
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct System {
     id: String,
     name: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    status: Option<String>,
     repo_name: String,
     path: String,
     description: Option<String>,
@@ -240,10 +376,13 @@ pub struct System {
     how_to: Vec<HowTo>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Subsystem {
     id: String,
     name: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    status: Option<String>,
     repo_name: String,
     path: String,
     description: Option<String>,
@@ -252,82 +391,628 @@ pub struct Subsystem {
 
     dependencies: Vec<SubsystemDependency>,
     how_to: Vec<HowTo>,
+
+    // Layout hints for graphviz
+    group: Option<String>,
+    rank: Option<String>,
+
+    /// How many subsystems depend on this one
+    in_degree: usize,
+    /// How many of its dependencies lead to a subsystem or a system
+    out_degree: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct HowTo {
     url: String,
     text: String,
+    /// None for the links without category
+    category: Option<String>,
 }
 
+/// A how-to link, with the system or subsystem it belongs to
 #[derive(Debug, Serialize)]
+pub struct HowToEntry<'a> {
+    #[serde(flatten)]
+    how_to: &'a HowTo,
+    /// `system` or `subsystem`
+    kind: &'static str,
+    id: &'a str,
+    name: &'a str,
+}
+
+/// A dependency leads to a subsystem or, if no subsystem has this id, to a whole system
+#[derive(Clone, Debug, Serialize)]
 pub struct SubsystemDependency {
     subsystem: ReferenceByIndex<Subsystem>,
+    /// Only given when the dependency leads to a whole system
+    #[serde(skip_serializing_if = "ReferenceByIndex::is_unresolved")]
+    system: ReferenceByIndex<System>,
     why: Option<String>,
+    weight: Option<u32>,
+    optional: bool,
+    since: Option<String>,
+    deprecated: Option<String>,
+}
+
+impl SubsystemDependency {
+    /// Whether the target has been found, as a subsystem or as a system
+    pub fn is_resolved(&self) -> bool {
+        self.subsystem.index().is_some() || self.system.index().is_some()
+    }
+}
+
+/// A system and everything stored in it, for the hierarchy representation
+#[derive(Debug, Serialize)]
+pub struct SystemNode {
+    id: String,
+    name: String,
+    systems: Vec<SystemNode>,
+    subsystems: Vec<SubsystemLeaf>,
+}
+
+/// A subsystem in the hierarchy representation. The details are in the flat representation.
+#[derive(Debug, Serialize)]
+pub struct SubsystemLeaf {
+    id: String,
+    name: String,
 }
 
+/// The systems nested in each other, as drawn by the clusters of the DOT output.
+/// The root holds the systems and subsystems without (known) parent.
 #[derive(Debug, Serialize)]
+pub struct Hierarchy {
+    systems: Vec<SystemNode>,
+    subsystems: Vec<SubsystemLeaf>,
+}
+
+/// The cluster gathering the subsystems without system, with `unassigned_cluster`
+const UNASSIGNED_CLUSTER_ID: &str = "__unassigned";
+
+/// Which dependencies are followed around a subsystem
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// What the subsystem depends on
+    Dependencies,
+    /// What depends on the subsystem
+    Dependants,
+    Both,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Graph {
     systems: Vec<System>,
     subsystems: Vec<Subsystem>,
     pub tool_version: String,
+    /// The files left out because they could not be read or parsed, listed in the issues
+    #[serde(skip)]
+    invalid_files: Vec<InvalidFile>,
 }
 
 impl Graph {
+    /// A graph without any system nor subsystem
+    pub fn empty() -> Graph {
+        Graph {
+            systems: Vec::new(),
+            subsystems: Vec::new(),
+            tool_version: built_info::PKG_VERSION.to_owned(),
+            invalid_files: Vec::new(),
+        }
+    }
+
     pub fn construct_from_config(
         config: &SiostamConfig,
     ) -> Result<Graph, Box<dyn std::error::Error>> {
-        // Get the data files
-        let mut list = Vec::new();
-        for target in config.targets.iter() {
-            // The path can be automatic (git repo) or local
-            let path: PathBuf;
-            let repo_name: String;
-
-            if target.folder.is_some() {
-                path = PathBuf::from(target.folder.as_ref().unwrap());
-                repo_name = path.as_os_str().to_string_lossy().to_string();
-
-                if !path.exists() {
-                    return Err(Box::from(CustomError::new(format!(
-                        "Local folder {} does not exists",
-                        path.display()
-                    ))));
-                } else {
-                    // The local folder mode is useful to quickly view the result but it is rather
-                    // error-prone. Displays warning to make sure the user knows it is located in local.
-                    warn!("Opened local folder {}", path.display());
-                }
-            } else if target.url.is_some() && target.branch.is_some() {
-                // Update/clone the repositories
-                let url = target.url.as_ref().unwrap();
-                let branch = target.branch.as_ref().unwrap();
-                repo_name = get_name_from_url(url.as_str()).to_owned();
-                path = get_git_repo_ready_for_extraction(&url, &branch, &repo_name)?;
-            } else {
-                error!("Target must have 'url' + 'branch' or 'folder'. Neither is available here");
-                continue;
-            };
+        Graph::construct_from_config_with(config, |_| {})
+    }
+
+    /// Same as `construct_from_config`, with a `transform` of the graph before it is output
+    /// (see `construct_incrementally_with`)
+    pub fn construct_from_config_with(
+        config: &SiostamConfig,
+        transform: impl FnMut(&mut Graph),
+    ) -> Result<Graph, Box<dyn std::error::Error>> {
+        let mut cache = TargetCache::default();
+        Graph::construct_incrementally_with(config, &mut cache, &Cancellation::default(), transform)
+    }
+
+    /// Construct the graph, reusing the parsed files of the git targets which did not change
+    /// since the previous construction. The cache is updated with the current files.
+    ///
+    /// Once `cancellation` is cancelled, it fails at the next step and the cache is left as is.
+    /// A clone or an update already started is not interrupted.
+    pub fn construct_incrementally(
+        config: &SiostamConfig,
+        cache: &mut TargetCache,
+        cancellation: &Cancellation,
+    ) -> Result<Graph, Box<dyn std::error::Error>> {
+        Graph::construct_incrementally_with(config, cache, cancellation, |_| {})
+    }
 
-            // Walk in the repositories to find the files
-            list.append(&mut extract_files_from_repo(
-                path.as_path(),
-                &repo_name,
-                config.suffix.as_str(),
-            ));
+    /// Same as `construct_incrementally`, with a hook to post-process the graph, e.g. to add
+    /// synthetic items, rename ids or prune some of them. The `transform` is applied once the files
+    /// are merged, then the links are reconstructed: it works on ids, not on indexes.
+    pub fn construct_incrementally_with(
+        config: &SiostamConfig,
+        cache: &mut TargetCache,
+        cancellation: &Cancellation,
+        mut transform: impl FnMut(&mut Graph),
+    ) -> Result<Graph, Box<dyn std::error::Error>> {
+        // Two targets in the same clone folder would overwrite each other
+        check_clone_folders(config)?;
+
+        // Get the repositories ready, with a bounded number of simultaneous fetches
+        // to stay friendly with the git hosts
+        let started = Instant::now();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(get_fetch_concurrency())
+            .build()?;
+        let previous_cache: &TargetCache = cache;
+        let targets: Result<Vec<_>, CustomError> = pool.install(|| {
+            config
+                .targets
+                .par_iter()
+                .map(|target| {
+                    cancellation.check()?;
+                    extract_target(target, config, previous_cache)
+                })
+                .collect()
+        });
+
+        // Gather the files and keep them for the next time.
+        // Only the current targets are kept in the cache.
+        let targets = targets?;
+        cancellation.check()?;
+        let mut new_cache = TargetCache::default();
+        let mut list = Vec::new();
+        let mut invalid_files = Vec::new();
+        for target in targets.into_iter().flatten() {
+            if let Some((key, commit)) = target.cache_entry {
+                let cached = CachedTarget {
+                    commit,
+                    sources: target.sources.clone(),
+                    invalid_files: target.invalid_files.clone(),
+                };
+                new_cache.insert(key, cached);
+            }
+            list.extend(target.sources);
+            invalid_files.extend(target.invalid_files);
+        }
+        *cache = new_cache;
+        info!(
+            "Found {} file(s) in {:.2?} (fetch, walk and parse of all targets)",
+            list.len(),
+            started.elapsed()
+        );
+
+        // An empty graph is almost always a mistyped suffix, rather than empty repositories
+        if list.is_empty() && invalid_files.is_empty() && !config.targets.is_empty() {
+            let message = format!(
+                "No file ends with the suffix `{}` in any of the {} target(s): is the suffix right?",
+                config.suffix,
+                config.targets.len()
+            );
+            if config.require_files {
+                return Err(CustomError::new(message).into());
+            }
+            warn!("{}", message);
         }
-        info!("Found {} file(s)", list.len());
 
         // Post-process the data
-        let graph = source_to_graph(list)?;
+        let linking = Instant::now();
+        let mut graph = source_to_graph(list, invalid_files, config)?;
+        transform(&mut graph);
+        reconstruct_links(&mut graph);
+        graph.check_size(config)?;
+        info!("Linked the graph in {:.2?}", linking.elapsed());
         debug!("{:#?}", graph);
         Ok(graph)
     }
 
+    /// Number of dependencies whose target has been found, i.e. the edges of the graph
+    pub fn edge_count(&self) -> usize {
+        self.subsystems
+            .iter()
+            .flat_map(|s| s.dependencies.iter())
+            .filter(|d| d.is_resolved())
+            .count()
+    }
+
+    /// Make sure the graph stays under the limits set in the configuration
+    pub fn check_size(&self, config: &SiostamConfig) -> Result<(), CustomError> {
+        let counts = [
+            ("systems", self.systems.len(), config.max_systems),
+            ("subsystems", self.subsystems.len(), config.max_subsystems),
+            ("edges", self.edge_count(), config.max_edges),
+        ];
+
+        for (kind, count, max) in counts.iter() {
+            if let Some(max) = max {
+                if count > max {
+                    return Err(CustomError::new(format!(
+                        "The graph has {} {} but the limit is {} (max_{}). \
+                         Is the suffix `{}` too broad? Try a more specific one.",
+                        count, kind, max, kind, config.suffix
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn to_json(&self) -> serde_json::Result<String> {
         serde_json::to_string_pretty(self)
     }
 
+    /// Same as to_json, without the whitespaces. Used by the server to reduce the bandwidth
+    pub fn to_compact_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Keep only the given systems and their descendants, with their subsystems and the
+    /// dependencies between them. The links are reconstructed on the remaining items.
+    pub fn retain_systems(&mut self, ids: &[String]) {
+        for id in ids
+            .iter()
+            .filter(|id| !self.systems.iter().any(|s| &s.id == *id))
+        {
+            warn!("Unknown system `{}` in include_systems", id);
+        }
+
+        // 1. A system is kept if it, or one of its ancestors, is listed
+        let is_kept = |index: usize| {
+            self.ancestors(Some(index))
+                .any(|i| ids.contains(&self.systems[i].id))
+        };
+        let kept_systems: Vec<bool> = (0..self.systems.len()).map(is_kept).collect();
+
+        // 2. A subsystem is kept if its parent is
+        let kept_subsystems: Vec<bool> = self
+            .subsystems
+            .iter()
+            .map(|s| match s.parent_system.as_ref().and_then(|p| p.index()) {
+                Some(parent) => kept_systems[parent],
+                None => false,
+            })
+            .collect();
+
+        // 3. Drop the rest, and the dependencies leading to it
+        let systems = std::mem::take(&mut self.systems);
+        self.systems = systems
+            .into_iter()
+            .zip(kept_systems.iter())
+            .filter(|(_, kept)| **kept)
+            .map(|(mut system, _)| {
+                // The listed systems become roots if their parent is dropped
+                let parent = system.parent_system.as_ref().and_then(|p| p.index());
+                if parent.is_some_and(|p| !kept_systems[p]) {
+                    system.parent_system = None;
+                }
+                system
+            })
+            .collect();
+        let subsystems = std::mem::take(&mut self.subsystems);
+        self.subsystems = subsystems
+            .into_iter()
+            .zip(kept_subsystems.iter())
+            .filter(|(_, kept)| **kept)
+            .map(|(mut subsystem, _)| {
+                subsystem
+                    .dependencies
+                    .retain(|d| match (d.subsystem.index(), d.system.index()) {
+                        (Some(target), _) => kept_subsystems[target],
+                        (None, Some(target)) => kept_systems[target],
+                        (None, None) => true,
+                    });
+                subsystem
+            })
+            .collect();
+
+        // 4. The indexes changed
+        reconstruct_links(self);
+    }
+
+    /// The subsystem `id` and the subsystems within `depth` dependencies of it, with their
+    /// systems. A system targeted by a dependency is kept, without its other subsystems.
+    /// None when no subsystem has this id.
+    pub fn neighborhood(&self, id: &str, depth: usize, direction: Direction) -> Option<Graph> {
+        let start = self.subsystems.iter().position(|s| s.id == id)?;
+
+        // 1. Walk the dependencies, one hop at a time
+        let mut kept_subsystems = vec![false; self.subsystems.len()];
+        let mut kept_systems = vec![false; self.systems.len()];
+        kept_subsystems[start] = true;
+        // The subsystems reached by the last hop, by index
+        let mut frontier = vec![false; self.subsystems.len()];
+        frontier[start] = true;
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for (index, subsystem) in self.subsystems.iter().enumerate() {
+                for dependency in subsystem.dependencies.iter() {
+                    let outgoing = direction != Direction::Dependants && frontier[index];
+                    match (dependency.subsystem.index(), dependency.system.index()) {
+                        (Some(target), _) => {
+                            let incoming = direction != Direction::Dependencies && frontier[target];
+                            if outgoing && !kept_subsystems[target] {
+                                kept_subsystems[target] = true;
+                                next.push(target);
+                            }
+                            if incoming && !kept_subsystems[index] {
+                                kept_subsystems[index] = true;
+                                next.push(index);
+                            }
+                        }
+                        (None, Some(target)) if outgoing => kept_systems[target] = true,
+                        _ => {}
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            frontier.iter_mut().for_each(|reached| *reached = false);
+            for index in next {
+                frontier[index] = true;
+            }
+        }
+
+        // 2. The systems containing what is kept are kept too, up to the root
+        let mut parents: Vec<Option<usize>> = self
+            .subsystems
+            .iter()
+            .zip(kept_subsystems.iter())
+            .filter(|(_, kept)| **kept)
+            .map(|(s, _)| s.parent_system.as_ref().and_then(|p| p.index()))
+            .collect();
+        parents.extend(
+            (0..self.systems.len())
+                .filter(|i| kept_systems[*i])
+                .map(Some),
+        );
+        for parent in parents {
+            for i in self.ancestors(parent) {
+                kept_systems[i] = true;
+            }
+        }
+
+        // 3. Copy what is kept, without the dependencies leading to the rest
+        let mut graph = Graph {
+            systems: self
+                .systems
+                .iter()
+                .zip(kept_systems.iter())
+                .filter(|(_, kept)| **kept)
+                .map(|(system, _)| system.clone())
+                .collect(),
+            subsystems: self
+                .subsystems
+                .iter()
+                .zip(kept_subsystems.iter())
+                .filter(|(_, kept)| **kept)
+                .map(|(subsystem, _)| {
+                    let mut subsystem = subsystem.clone();
+                    subsystem.dependencies.retain(|d| {
+                        match (d.subsystem.index(), d.system.index()) {
+                            (Some(target), _) => kept_subsystems[target],
+                            (None, Some(target)) => kept_systems[target],
+                            (None, None) => false,
+                        }
+                    });
+                    subsystem
+                })
+                .collect(),
+            tool_version: self.tool_version.clone(),
+            invalid_files: Vec::new(),
+        };
+
+        // 4. The indexes changed
+        reconstruct_links(&mut graph);
+        Some(graph)
+    }
+
+    /// Replace the repo_name and path of every item by a hash, so that a published graph does not
+    /// reveal how the repositories are organized. The items of a file still share the same hashes.
+    /// The hashes are salted: a guessed path can't be hashed to be compared with them
+    pub fn anonymize_paths(&mut self) {
+        let salt = ANONYMIZE_SALT.as_str();
+        let anonymize = |repo_name: &mut String, path: &mut String| {
+            *path = content_hash(format!("{}{}/{}", salt, repo_name, path).as_str());
+            *repo_name = content_hash(format!("{}{}", salt, repo_name).as_str());
+        };
+        for system in self.systems.iter_mut() {
+            anonymize(&mut system.repo_name, &mut system.path);
+        }
+        for subsystem in self.subsystems.iter_mut() {
+            anonymize(&mut subsystem.repo_name, &mut subsystem.path);
+        }
+        // The errors quote the paths: they are only in the logs
+        for invalid_file in self.invalid_files.iter_mut() {
+            anonymize(&mut invalid_file.repo_name, &mut invalid_file.path);
+            invalid_file.error = HIDDEN_BY_ANONYMIZATION.to_owned();
+        }
+    }
+
+    /// Put the systems and subsystems without parent in a system named after the first segment
+    /// of their id, e.g. `payments` for `payments.api`. A missing system is created, without
+    /// file. The links must be reconstructed afterwards.
+    pub fn group_by_id_prefix(&mut self, separator: &str) {
+        if separator.is_empty() {
+            return;
+        }
+        let prefix_of = |id: &str| -> Option<String> {
+            let (prefix, _) = id.split_once(separator)?;
+            Some(prefix.to_owned()).filter(|p| !p.is_empty())
+        };
+
+        let mut prefixes = Vec::new();
+        for system in self.systems.iter_mut() {
+            match prefix_of(&system.id) {
+                Some(prefix) if system.parent_system.is_none() => {
+                    system.parent_system = Some(ReferenceByIndex::new(&prefix));
+                    prefixes.push(prefix);
+                }
+                _ => {}
+            }
+        }
+        for subsystem in self.subsystems.iter_mut() {
+            match prefix_of(&subsystem.id) {
+                Some(prefix) if subsystem.parent_system.is_none() => {
+                    subsystem.parent_system = Some(ReferenceByIndex::new(&prefix));
+                    prefixes.push(prefix);
+                }
+                _ => {}
+            }
+        }
+
+        for prefix in prefixes {
+            if self.systems.iter().all(|system| system.id != prefix) {
+                self.systems.push(System {
+                    id: prefix.clone(),
+                    name: prefix,
+                    kind: None,
+                    status: None,
+                    repo_name: String::new(),
+                    path: String::new(),
+                    description: None,
+                    parent_system: None,
+                    how_to: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Give the systems and subsystems without how-to the ones of their closest parent system
+    /// having some. The items with their own how-tos are left untouched. The links must be
+    /// reconstructed beforehand.
+    pub fn inherit_how_tos(&mut self) {
+        let inherited_from = |parent: Option<&ReferenceByIndex<System>>| {
+            self.ancestors(parent.and_then(|p| p.index()))
+                .find(|i| !self.systems[*i].how_to.is_empty())
+        };
+
+        let system_sources: Vec<Option<usize>> = self
+            .systems
+            .iter()
+            .map(|s| {
+                if s.how_to.is_empty() {
+                    inherited_from(s.parent_system.as_ref())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let subsystem_sources: Vec<Option<usize>> = self
+            .subsystems
+            .iter()
+            .map(|s| {
+                if s.how_to.is_empty() {
+                    inherited_from(s.parent_system.as_ref())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for (index, source) in system_sources.into_iter().enumerate() {
+            if let Some(source) = source {
+                self.systems[index].how_to = self.systems[source].how_to.clone();
+            }
+        }
+        for (subsystem, source) in self.subsystems.iter_mut().zip(subsystem_sources) {
+            if let Some(source) = source {
+                subsystem.how_to = self.systems[source].how_to.clone();
+            }
+        }
+    }
+
+    /// List the how-to links of every system and subsystem, e.g. to check each has a runbook
+    pub fn how_tos(&self) -> Vec<HowToEntry<'_>> {
+        let systems = self
+            .systems
+            .iter()
+            .map(|s| ("system", &s.id, &s.name, &s.how_to));
+        let subsystems = self
+            .subsystems
+            .iter()
+            .map(|s| ("subsystem", &s.id, &s.name, &s.how_to));
+        systems
+            .chain(subsystems)
+            .flat_map(|(kind, id, name, how_to)| {
+                how_to.iter().map(move |how_to| HowToEntry {
+                    how_to,
+                    kind,
+                    id,
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// Output the how-to links of the whole graph, with their system or subsystem, in JSON
+    pub fn how_tos_to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.how_tos())
+    }
+
+    /// Output only the systems, with their parent, in JSON
+    pub fn systems_to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.systems)
+    }
+
+    /// Output the systems as a tree, in JSON
+    pub fn hierarchy_to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.hierarchy())
+    }
+
+    /// Nest the systems and subsystems into their parent
+    pub fn hierarchy(&self) -> Hierarchy {
+        let (systems, subsystems) = self.hierarchy_children(None);
+        Hierarchy {
+            systems,
+            subsystems,
+        }
+    }
+
+    /// Recursively collect the systems and subsystems with the given parent,
+    /// the same way output_system walks them
+    fn hierarchy_children(
+        &self,
+        current_parent_index: Option<usize>,
+    ) -> (Vec<SystemNode>, Vec<SubsystemLeaf>) {
+        // 1. The systems with the given parent, and their own children
+        let systems = self
+            .systems
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| {
+                s.parent_system.as_ref().and_then(|p| p.index()) == current_parent_index
+            })
+            .map(|(index, system)| {
+                let (systems, subsystems) = self.hierarchy_children(Some(index));
+                SystemNode {
+                    id: system.id.clone(),
+                    name: system.name.clone(),
+                    systems,
+                    subsystems,
+                }
+            })
+            .collect();
+
+        // 2. The subsystems with the given parent
+        let subsystems = self
+            .subsystems
+            .iter()
+            .filter(|s| s.parent_system.as_ref().and_then(|p| p.index()) == current_parent_index)
+            .map(|subsystem| SubsystemLeaf {
+                id: subsystem.id.clone(),
+                name: subsystem.name.clone(),
+            })
+            .collect();
+
+        (systems, subsystems)
+    }
+
     /// Outputs all the data as JSON for the front-end
     pub fn output_to_json(&self, path: &str) -> serde_json::Result<()> {
         fs::write(path, self.to_json()?).expect("Error with the json output");
@@ -335,8 +1020,19 @@ impl Graph {
     }
 
     /// Output the graph as DOT
-    pub fn output_to_dot(&self, path: &str) -> io::Result<()> {
-        let mut dot = DotBuilder::new(path)?;
+    pub fn output_to_dot(&self, path: &str, render: &RenderConfig) -> io::Result<()> {
+        self.write_dot(DotBuilder::create(path, render)?, render)?;
+        Ok(())
+    }
+
+    /// Output the graph as a DOT string, without touching the disk
+    pub fn to_dot(&self, render: &RenderConfig) -> io::Result<String> {
+        let buffer = self.write_dot(DotBuilder::new(Vec::new(), render)?, render)?;
+        Ok(String::from_utf8_lossy(buffer.as_slice()).to_string())
+    }
+
+    /// Output the graph with the given builder and give back its writer
+    fn write_dot<W: Write>(&self, mut dot: DotBuilder<W>, render: &RenderConfig) -> io::Result<W> {
         let indent = "  ";
 
         // Generate the systems + subsystems, but not the edges.
@@ -344,22 +1040,33 @@ impl Graph {
         // That's why the links are added at root
 
         // 1. Recursively generate systems (clusters) and subsystems (nodes)
-        self.output_system(&mut dot, None, indent)?;
+        // In flat mode, there is no cluster: all subsystems are at the root
+        if render.flat {
+            for subsystem in self.subsystems.iter() {
+                self.output_subsystem(&mut dot, subsystem, indent);
+            }
+        } else {
+            let anchored = self.systems_targeted_as_a_whole();
+            self.output_system(&mut dot, None, indent, render.unassigned_cluster, &anchored)?;
+        }
         // 2. Add subsystems' dependencies (edges)
-        self.output_subsystems_dependencies(&mut dot, indent)?;
+        self.output_subsystems_dependencies(&mut dot, indent, render.flat)?;
 
-        // Print the end of file and close it
-        dot.close()?;
+        // 3. Add the layout hints
+        self.output_subsystems_ranks(&mut dot, indent);
 
-        Ok(())
+        // Print the end of file and close it
+        dot.close()
     }
 
     /// Recursively output systems and subsytems as DOT
-    fn output_system(
+    fn output_system<W: Write>(
         &self,
-        mut dot: &mut DotBuilder,
+        dot: &mut DotBuilder<W>,
         current_parent_index: Option<usize>,
         indent: &str,
+        unassigned_cluster: bool,
+        anchored: &[bool],
     ) -> io::Result<()> {
         // 1. We search for systems with a given parent
         // We begin with current_parent_index = None, which is the root of the graph
@@ -368,43 +1075,375 @@ impl Graph {
             let parent_system_index = system.parent_system.as_ref().and_then(|p| p.index());
             if parent_system_index == current_parent_index {
                 // Begin a new cluster
-                dot.begin_cluster(&indent, &system.id, &system.name);
+                dot.begin_cluster(indent, &system.id, &system.name, system.status.as_deref());
+
+                // The edges to the whole system end at its anchor
+                let inner_indent = format!("{}  ", indent);
+                if anchored[index] {
+                    dot.add_cluster_anchor(&inner_indent, &system.id);
+                }
 
                 // Display children systems
-                self.output_system(&mut dot, Some(index), format!("{}  ", indent).as_str())?;
+                self.output_system(dot, Some(index), &inner_indent, false, anchored)?;
 
                 // Close the cluster
-                dot.end_cluster(&indent);
+                dot.end_cluster(indent);
             }
         }
 
         // 2. We search for subsystems with a given parent
+        // The ones without parent may be gathered in their own cluster, to be noticed
+        if current_parent_index.is_none() && unassigned_cluster {
+            self.output_unassigned_subsystems(dot, indent);
+            return Ok(());
+        }
         for subsystem in self.subsystems.iter() {
             // Again, we use the parent_system index to find if it is targeted or not
             let parent_system_index = subsystem.parent_system.as_ref().and_then(|p| p.index());
             if parent_system_index == current_parent_index {
-                dot.add_node(&indent, &subsystem.id, &subsystem.name);
+                self.output_subsystem(dot, subsystem, indent);
             }
         }
 
         Ok(())
     }
 
+    /// Output the subsystems without parent, or with an unknown one, in an `Unassigned` cluster
+    fn output_unassigned_subsystems<W: Write>(&self, dot: &mut DotBuilder<W>, indent: &str) {
+        let unassigned: Vec<&Subsystem> = self
+            .subsystems
+            .iter()
+            .filter(|s| s.parent_system.as_ref().and_then(|p| p.index()).is_none())
+            .collect();
+        if unassigned.is_empty() {
+            return;
+        }
+
+        // The id can't be used by a system: ids are not expected to start with underscores
+        dot.begin_cluster(indent, UNASSIGNED_CLUSTER_ID, "Unassigned", None);
+        let inner_indent = format!("{}  ", indent);
+        for subsystem in unassigned {
+            self.output_subsystem(dot, subsystem, &inner_indent);
+        }
+        dot.end_cluster(indent);
+    }
+
+    /// Output a single subsystem as a DOT node
+    fn output_subsystem<W: Write>(
+        &self,
+        dot: &mut DotBuilder<W>,
+        subsystem: &Subsystem,
+        indent: &str,
+    ) {
+        let attributes = NodeAttributes {
+            description: subsystem.description.as_deref(),
+            // The primary how-to is the first one
+            url: subsystem.how_to.first().map(|h| h.url.as_str()),
+            group: subsystem.group.as_deref(),
+            kind: subsystem.kind.as_deref(),
+            status: subsystem.status.as_deref(),
+        };
+        dot.add_node(indent, &subsystem.id, &subsystem.name, attributes);
+    }
+
+    /// Print the subsystems sharing a rank as `rank = same` groups
+    /// They are at the root, like the edges, because they may be in different clusters
+    fn output_subsystems_ranks<W: Write>(&self, dot: &mut DotBuilder<W>, indent: &str) {
+        // Group the ids by rank, in order of appearance
+        let mut ranks: Vec<(&str, Vec<&str>)> = Vec::new();
+        for subsystem in self.subsystems.iter() {
+            if let Some(rank) = subsystem.rank.as_deref() {
+                match ranks.iter_mut().find(|(name, _)| *name == rank) {
+                    Some((_, ids)) => ids.push(subsystem.id.as_str()),
+                    None => ranks.push((rank, vec![subsystem.id.as_str()])),
+                }
+            }
+        }
+
+        for (_, ids) in ranks.iter().filter(|(_, ids)| ids.len() > 1) {
+            dot.add_same_rank(indent, ids);
+        }
+    }
+
     /// Print dependencies between subsystems as DOT
-    fn output_subsystems_dependencies(&self, dot: &mut DotBuilder, indent: &str) -> io::Result<()> {
+    fn output_subsystems_dependencies<W: Write>(
+        &self,
+        dot: &mut DotBuilder<W>,
+        indent: &str,
+        flat: bool,
+    ) -> io::Result<()> {
         // Parse all subsystems dependencies
         for subsystem_a in self.subsystems.iter() {
             for dependency in subsystem_a.dependencies.iter() {
+                let attributes = EdgeAttributes {
+                    weight: dependency.weight,
+                    optional: dependency.optional,
+                    deprecated: dependency.deprecated.is_some(),
+                };
+
                 // Search for the targeted system. If there is one output it
                 if let Some(subsystem_b) = dependency.subsystem.index().map(|s| &self.subsystems[s])
                 {
-                    dot.add_edge(&indent, &subsystem_a.id, &subsystem_b.id);
+                    dot.add_edge(indent, &subsystem_a.id, &subsystem_b.id, attributes);
+                } else if let Some(system) = dependency.system.index() {
+                    // An edge needs a node: it goes to the invisible anchor of the cluster, or
+                    // to the first subsystem of the system in flat mode
+                    match self.first_subsystem_in(system) {
+                        Some(subsystem_b) if flat => {
+                            dot.add_edge(indent, &subsystem_a.id, &subsystem_b.id, attributes)
+                        }
+                        Some(_) => dot.add_edge_to_cluster(
+                            indent,
+                            &subsystem_a.id,
+                            &self.systems[system].id,
+                            attributes,
+                        ),
+                        None => debug!(
+                            "No edge from {} to the empty system {}",
+                            subsystem_a.id, self.systems[system].id
+                        ),
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    /// Which systems are the target of a dependency, and have a subsystem to be drawn
+    fn systems_targeted_as_a_whole(&self) -> Vec<bool> {
+        let mut targeted = vec![false; self.systems.len()];
+        for dependency in self.subsystems.iter().flat_map(|s| s.dependencies.iter()) {
+            if let (None, Some(system)) = (dependency.subsystem.index(), dependency.system.index())
+            {
+                if !targeted[system] {
+                    targeted[system] = self.first_subsystem_in(system).is_some();
+                }
+            }
+        }
+        targeted
+    }
+
+    /// The given parent system, then its own parent and so on up to the root.
+    /// The walk is bounded in case parent systems loop
+    fn ancestors(&self, parent: Option<usize>) -> impl Iterator<Item = usize> + '_ {
+        iter::successors(parent, move |i| {
+            self.systems[*i]
+                .parent_system
+                .as_ref()
+                .and_then(|p| p.index())
+        })
+        .take(self.systems.len())
+    }
+
+    /// The first subsystem stored in the system or in one of its descendants
+    fn first_subsystem_in(&self, system_index: usize) -> Option<&Subsystem> {
+        self.subsystems.iter().find(|subsystem| {
+            self.ancestors(subsystem.parent_system.as_ref().and_then(|p| p.index()))
+                .any(|i| i == system_index)
+        })
+    }
+}
+
+/// The parsed files of a target
+struct ExtractedTarget {
+    sources: Vec<SubsystemFileSource>,
+    invalid_files: Vec<InvalidFile>,
+    /// For git targets, the cache key and the commit the files come from
+    cache_entry: Option<(String, String)>,
+}
+
+/// Clone/update the target if required, then find and parse its files.
+/// Git targets whose branch did not move since the last time are taken from the cache.
+/// Invalid targets are ignored
+fn extract_target(
+    target: &Target,
+    config: &SiostamConfig,
+    cache: &TargetCache,
+) -> Result<Option<ExtractedTarget>, CustomError> {
+    // The paths can be automatic (git repo) or local, with the name of the "repository" of each
+    let roots: Vec<(PathBuf, String)>;
+    let mut cache_entry = None;
+    let mut is_git = false;
+    // The unpacked archive, removed once its files are parsed
+    let mut _unpacked = None;
+
+    if let Some(folder) = target.folder.as_ref() {
+        let paths = if is_glob(folder) {
+            let paths = expand_folder_glob(folder)?;
+            if paths.is_empty() {
+                return Err(CustomError::new(format!(
+                    "Local folder pattern {} matches no folder",
+                    folder
+                )));
+            }
+            paths
+        } else {
+            vec![PathBuf::from(folder)]
+        };
+
+        for path in paths.iter() {
+            if !path.exists() {
+                return Err(CustomError::new(format!(
+                    "Local folder {} does not exists",
+                    path.display()
+                )));
+            } else {
+                // The local folder mode is useful to quickly view the result but it is rather
+                // error-prone. Displays warning to make sure the user knows it is located in local.
+                warn!("Opened local folder {}", path.display());
+            }
+        }
+        roots = paths
+            .into_iter()
+            .map(|path| {
+                let repo_name = path.as_os_str().to_string_lossy().to_string();
+                (path, repo_name)
+            })
+            .collect();
+    } else if let Some(archive) = target.archive.as_ref() {
+        let unpacked = unpack_archive(Path::new(archive))?;
+        info!(
+            "Unpacked archive {} in {}",
+            archive,
+            unpacked.path().display()
+        );
+        let repo_name = Path::new(archive)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| archive.clone());
+        roots = vec![(unpacked.path().to_owned(), repo_name)];
+        _unpacked = Some(unpacked);
+    } else if let Some(url) = target.url.as_ref() {
+        // Update/clone the repositories
+        let branches = target.branches();
+        let repo_name = get_name_from_url(url.as_str()).to_owned();
+
+        // Skip the fetch if the remote branch is still on the commit we already parsed.
+        // A kept working tree may be edited by hand: it is parsed again every time
+        let key = TargetCache::key(url, branches);
+        let cached = if keep_worktree() {
+            None
+        } else {
+            cache.get(&key)
+        };
+        if let Some(cached) = cached {
+            match get_remote_commit(branches, &repo_name) {
+                Ok(Some(commit)) if commit == cached.commit => {
+                    info!("Repository {} unchanged since {}", repo_name, commit);
+                    return Ok(Some(ExtractedTarget {
+                        sources: cached.sources.clone(),
+                        invalid_files: cached.invalid_files.clone(),
+                        cache_entry: Some((key, commit)),
+                    }));
+                }
+                Ok(_) => {}
+                Err(err) => warn!("While checking remote of {}: {}", repo_name, err),
+            }
+        }
+
+        let fetching = Instant::now();
+        let (repo_path, commit) = get_git_repo_ready_for_extraction(
+            url,
+            branches,
+            &repo_name,
+            target.recurse_submodules,
+            config.max_repo_size,
+        )?;
+        debug!("Fetched {} in {:.2?}", repo_name, fetching.elapsed());
+        roots = vec![(repo_path, repo_name)];
+        is_git = true;
+        if !keep_worktree() {
+            cache_entry = Some((key, commit));
+        }
+    } else {
+        error!("Target must have 'url', 'folder' or 'archive'. None is available here");
+        return Ok(None);
+    };
+
+    // A folder pattern gives several roots, each one walked on its own
+    let mut sources = Vec::new();
+    let mut invalid_files = Vec::new();
+    for (path, repo_name) in roots.iter() {
+        let (mut root_sources, mut root_invalid_files) =
+            extract_root(path.as_path(), repo_name, config, is_git)?;
+        sources.append(&mut root_sources);
+        invalid_files.append(&mut root_invalid_files);
+    }
+
+    Ok(Some(ExtractedTarget {
+        sources,
+        invalid_files,
+        cache_entry,
+    }))
+}
+
+/// Find and parse the files of a folder, a clone or a match of a folder pattern
+fn extract_root(
+    path: &Path,
+    repo_name: &str,
+    config: &SiostamConfig,
+    is_git: bool,
+) -> Result<(Vec<SubsystemFileSource>, Vec<InvalidFile>), CustomError> {
+    // Walk in the repositories to find the files
+    let walking = Instant::now();
+    let files = extract_files_from_repo(
+        path,
+        repo_name,
+        config.suffix.as_str(),
+        &config.exact_names,
+        config.case_insensitive_suffix,
+    );
+    debug!("Walked {} in {:.2?}", repo_name, walking.elapsed());
+
+    // Audit views: only the files last changed by someone or since a date
+    let files = match config.commit_filter.as_ref() {
+        Some(filter) if is_git => {
+            let matched = files.len();
+            let files = filter_files_by_last_commit(files, path, filter)?;
+            info!(
+                "{}: {} of {} file(s) kept by the commit filter",
+                repo_name,
+                files.len(),
+                matched
+            );
+            files
+        }
+        Some(_) => {
+            warn!(
+                "{} is a local folder: the commit filter does not apply",
+                repo_name
+            );
+            files
+        }
+        None => files,
+    };
+
+    let parsing = Instant::now();
+    let (sources, errors) = read_files(&files);
+    debug!(
+        "Parsed {} file(s) of {} in {:.2?}",
+        files.len(),
+        repo_name,
+        parsing.elapsed()
+    );
+
+    // Per-target counts, to tell a wrong suffix from an invalid file at a glance
+    info!(
+        "{}: {} file(s) matched, {} parsed, {} failed",
+        repo_name,
+        files.len(),
+        sources.len(),
+        errors.len()
+    );
+    if files.is_empty() {
+        warn!(
+            "No file ending with `{}` in {}. Is the suffix right?",
+            config.suffix, repo_name
+        );
+    }
+
+    Ok((sources, errors))
 }
 
 /// Read the content and parse it as TOML
@@ -424,35 +1463,152 @@ pub fn read_file(subsystem_file: &SubsystemFile) -> Result<SubsystemFileSource,
 
     content.repo_name = Some(subsystem_file.repo_name.clone());
     content.path = Some(subsystem_file.relative_path.clone());
+    content.directory = subsystem_file.path.parent().map(|p| p.to_path_buf());
+    content.root = Some(subsystem_file.root.clone());
     Ok(content)
 }
 
-/// Read the files and reconstruct the whole graph from them
-pub fn source_to_graph(files: Vec<SubsystemFile>) -> Result<Graph, CustomError> {
-    // First, we read the files and store each system, subsystem
-    let mut graph = merge_all_files(files)?;
+/// Read the content of the files as TOML
+/// Each file is independent so they are parsed in parallel. The collect keeps the input order.
+/// The successfully parsed files are kept apart from the invalid ones
+pub fn read_files(files: &[SubsystemFile]) -> (Vec<SubsystemFileSource>, Vec<InvalidFile>) {
+    let results: Vec<Result<SubsystemFileSource, CustomError>> =
+        files.par_iter().map(read_file).collect();
+
+    let mut sources = Vec::with_capacity(results.len());
+    let mut invalid_files = Vec::new();
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(source) => sources.push(source),
+            Err(err) => invalid_files.push(InvalidFile {
+                repo_name: file.repo_name.clone(),
+                path: file.relative_path.clone(),
+                error: err.message,
+            }),
+        }
+    }
+    (sources, invalid_files)
+}
+
+/// With `strict_files`, a single invalid file fails the whole update, with the message of every
+/// invalid file. Otherwise they are only logged, and listed in the issues of the graph
+fn check_invalid_files(invalid_files: &[InvalidFile], strict: bool) -> Result<(), CustomError> {
+    if invalid_files.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        let lines: Vec<String> = invalid_files.iter().map(|f| format!("- {}", f)).collect();
+        return Err(CustomError::new(format!(
+            "{} invalid subsystem file(s) while `strict_files` is enabled:\n{}",
+            invalid_files.len(),
+            lines.join("\n")
+        )));
+    }
+    for invalid_file in invalid_files.iter() {
+        warn!("Left out of the graph: {}", invalid_file);
+    }
+    Ok(())
+}
+
+/// Reconstruct the whole graph from the parsed files
+/// The `invalid_files` are the ones which could not be parsed, to be reported with the others
+pub fn source_to_graph(
+    files: Vec<SubsystemFileSource>,
+    invalid_files: Vec<InvalidFile>,
+    config: &SiostamConfig,
+) -> Result<Graph, CustomError> {
+    // First, we store each system, subsystem. A file with an invalid content is left out as well
+    let mut graph = merge_all_files(files);
+    graph.invalid_files.splice(0..0, invalid_files);
+    check_invalid_files(&graph.invalid_files, config.strict_files)?;
+
+    // Structure for the flat id schemes, before the links are made
+    if let Some(separator) = config.group_by_id_prefix.as_ref() {
+        graph.group_by_id_prefix(separator);
+    }
+
+    // Governance: refuse the dependencies which do not explain themselves
+    if config.require_dependency_why {
+        check_dependencies_why(&graph)?;
+    }
+
+    // An id shared by a system and a subsystem is almost always a mistake
+    for shared in find_ids_shared_by_system_and_subsystem(&graph) {
+        warn!(
+            "`{}` is used as both a system and a subsystem id in {}",
+            shared.id,
+            shared.files.join(", ")
+        );
+    }
 
     // Then, we use the ids to link system and subsystems together
     reconstruct_links(&mut graph);
 
+    // Governance: refuse the references which do not lead anywhere
+    let unresolved = find_unresolved_references(&graph);
+    if config.strict_links && !unresolved.is_empty() {
+        let lines: Vec<String> = unresolved.iter().map(|r| format!("- {}", r)).collect();
+        return Err(CustomError::new(format!(
+            "{} unresolved reference(s) while `strict_links` is enabled:\n{}",
+            unresolved.len(),
+            lines.join("\n")
+        )));
+    }
+
+    // A missing parent silently moves the item to the root of the graph: tell why it is there
+    for reference in unresolved.iter().filter(|r| r.reference == "parent_system") {
+        warn!("{}: it is drawn at the root of the graph", reference);
+    }
+
+    // Documentation shared by a whole system, once the parents are known
+    if config.inherit_howtos {
+        graph.inherit_how_tos();
+    }
+
+    // Curated subset: only the listed systems, their descendants and the edges among them
+    if !config.include_systems.is_empty() {
+        graph.retain_systems(&config.include_systems);
+    }
+
+    // Lint: the systems linked to nothing are usually a mistake, but may be intentional
+    if config.warn_orphan_systems {
+        for orphan in find_orphan_systems(&graph) {
+            warn!("Orphan {}", orphan);
+        }
+    }
+
+    // Last: the checks above name the real files in the logs
+    if config.anonymize_paths {
+        graph.anonymize_paths();
+    }
+
     Ok(graph)
 }
 
 /// Get all systems/subsystems from the files
-fn merge_all_files(files: Vec<SubsystemFile>) -> Result<Graph, CustomError> {
-    // Read the content of the files as TOML
-    let files: Result<Vec<_>, _> = files.iter().map(|f| read_file(f)).collect();
-    let files = files?;
-
+fn merge_all_files(files: Vec<SubsystemFileSource>) -> Graph {
     // WARNING: items in these Vec<> must only be added at the end to preserve indexes.
     let mut systems: Vec<System> = Vec::new();
     let mut subsystems: Vec<Subsystem> = Vec::new();
+    let mut invalid_files: Vec<InvalidFile> = Vec::new();
 
     // Process each file
     for file in files {
+        let invalid_file = |err: CustomError| InvalidFile {
+            repo_name: file.repo_name.clone().unwrap_or_default(),
+            path: file.path.clone().unwrap_or_default(),
+            error: err.message,
+        };
+
         // First we need the system.
         // If there is one specified, it will be considered as the subsystems parent
-        let system = file.extract_system();
+        let system = match file.extract_system() {
+            Ok(system) => system,
+            Err(err) => {
+                invalid_files.push(invalid_file(err));
+                continue;
+            }
+        };
 
         // Get the id of the local parent for the subsystems:
         //  - the system if there is one
@@ -462,7 +1618,13 @@ fn merge_all_files(files: Vec<SubsystemFile>) -> Result<Graph, CustomError> {
         let subsystem_parent = system_id.or(file.stored_in_system.as_ref());
 
         // Get the subsystems
-        let mut local_subsystems: Vec<Subsystem> = file.extract_subsystems(subsystem_parent);
+        let mut local_subsystems: Vec<Subsystem> = match file.extract_subsystems(subsystem_parent) {
+            Ok(subsystems) => subsystems,
+            Err(err) => {
+                invalid_files.push(invalid_file(err));
+                continue;
+            }
+        };
 
         // Add the systems/subsystems to the list
         if system.is_some() {
@@ -471,11 +1633,77 @@ fn merge_all_files(files: Vec<SubsystemFile>) -> Result<Graph, CustomError> {
         subsystems.append(&mut local_subsystems);
     }
 
-    Ok(Graph {
+    Graph {
         systems,
         subsystems,
         tool_version: built_info::PKG_VERSION.to_owned(),
-    })
+        invalid_files,
+    }
+}
+
+/// Return an error listing the git targets which would be cloned in the same `data/<name>`
+fn check_clone_folders(config: &SiostamConfig) -> Result<(), CustomError> {
+    let mut urls_by_folder: Vec<(&str, Vec<&str>)> = Vec::new();
+    let urls = config
+        .targets
+        .iter()
+        .filter(|t| t.folder.is_none())
+        .filter_map(|t| t.url.as_deref());
+    for url in urls {
+        let name = get_name_from_url(url);
+        match urls_by_folder
+            .iter_mut()
+            .find(|(folder, _)| *folder == name)
+        {
+            Some((_, urls)) => urls.push(url),
+            None => urls_by_folder.push((name, vec![url])),
+        }
+    }
+
+    let conflicts: Vec<String> = urls_by_folder
+        .iter()
+        .filter(|(_, urls)| urls.len() > 1)
+        .map(|(folder, urls)| format!("- data/{}: {}", folder, urls.join(", ")))
+        .collect();
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(CustomError::new(format!(
+            "{} clone folder(s) shared by several targets, which would overwrite each other:\n{}",
+            conflicts.len(),
+            conflicts.join("\n")
+        )))
+    }
+}
+
+/// Return an error listing every dependency declared without a `why`
+fn check_dependencies_why(graph: &Graph) -> Result<(), CustomError> {
+    let missing: Vec<String> = graph
+        .subsystems
+        .iter()
+        .flat_map(|s| s.dependencies.iter().map(move |d| (s, d)))
+        .filter(|(_, dependency)| dependency.why.is_none())
+        .map(|(subsystem, dependency)| {
+            format!(
+                "- `{}` -> `{}` in {}/{}",
+                subsystem.id,
+                dependency.subsystem.id(),
+                subsystem.repo_name,
+                subsystem.path
+            )
+        })
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(CustomError::new(format!(
+            "{} dependency(ies) without `why` while `require_dependency_why` is enabled:\n{}",
+            missing.len(),
+            missing.join("\n")
+        )))
+    }
 }
 
 // Parse each ReferenceByIndex and search for the target in the graph
@@ -505,57 +1733,1064 @@ fn reconstruct_links(unlinked_graph: &mut Graph) {
         .filter_map(|s| s.parent_system.as_mut())
         .for_each(|parent| parent.find_index_in(&systems));
 
-    // 2. For subsystems' dependencies
+    // 2. For subsystems' dependencies. A subsystem takes precedence over a system with the same id
     unlinked_graph
         .subsystems
         .iter_mut()
         .flat_map(|s: &mut Subsystem| s.dependencies.iter_mut())
-        .map(|dep: &mut SubsystemDependency| dep.subsystem.borrow_mut())
-        .for_each(|parent: &mut ReferenceByIndex<Subsystem>| parent.find_index_in(&subsystems));
+        .for_each(|dep: &mut SubsystemDependency| {
+            dep.subsystem.find_index_in(&subsystems);
+            if dep.subsystem.index().is_some() {
+                dep.system.clear_index();
+            } else {
+                dep.system.find_index_in(&systems);
+            }
+        });
+
+    // 3. Count the resolved dependencies, for the clients looking for the most used subsystems
+    let mut in_degrees = vec![0; unlinked_graph.subsystems.len()];
+    for dependency in unlinked_graph
+        .subsystems
+        .iter()
+        .flat_map(|s| s.dependencies.iter())
+    {
+        if let Some(target) = dependency.subsystem.index() {
+            in_degrees[target] += 1;
+        }
+    }
+    for (subsystem, in_degree) in unlinked_graph.subsystems.iter_mut().zip(in_degrees) {
+        subsystem.in_degree = in_degree;
+        subsystem.out_degree = subsystem
+            .dependencies
+            .iter()
+            .filter(|d| d.is_resolved())
+            .count();
+    }
 }
 
-#[derive(Eq, PartialEq)]
 pub struct GraphRepresentation {
-    json: String,
-    svg: String,
+    /// The graph itself, for the endpoints working on typed data.
+    /// Shared so that a long response does not have to hold the lock of the core
+    graph: Arc<Graph>,
+    /// Hash of the JSON: the same content always has the same hash, even across restarts.
+    /// The JSON itself is streamed on demand, it is not kept
+    content_hash: String,
+    /// None when the SVG generation is disabled
+    dot: Option<String>,
+    svg: Option<String>,
+    svg_dimensions: SvgDimensions,
+    issues: GraphIssues,
+    /// Whether the SVG is drawn by graphviz, which the other layout engines need
+    graphviz: bool,
+    /// The SVGs rendered on demand with another layout engine, by engine
+    engine_svgs: Mutex<HashMap<String, String>>,
+}
+
+/// The JSON holds the whole graph: no need to compare the graphs themselves, nor their JSON
+/// once hashed
+impl PartialEq for GraphRepresentation {
+    fn eq(&self, other: &Self) -> bool {
+        self.content_hash == other.content_hash && self.dot == other.dot && self.svg == other.svg
+    }
+}
+
+impl Eq for GraphRepresentation {}
+
+/// Replaces the errors quoting real paths when `anonymize_paths` is enabled
+pub const HIDDEN_BY_ANONYMIZATION: &str = "hidden by `anonymize_paths`, see the logs";
+
+lazy_static! {
+    /// Salt of the anonymized paths. `SiostamConfig::validate` requires it with `anonymize_paths`,
+    /// so the random one is only drawn for the graphs built without validation, e.g. in tests
+    static ref ANONYMIZE_SALT: String = anonymize_salt_from_env()
+        .unwrap_or_else(|| format!("{:016x}", RandomState::new().build_hasher().finish()));
+}
+
+/// The salt of the anonymized paths, from SIOSTAM_ANONYMIZE_SALT. None when not set or empty
+pub fn anonymize_salt_from_env() -> Option<String> {
+    env::var("SIOSTAM_ANONYMIZE_SALT")
+        .ok()
+        .filter(|salt| !salt.is_empty())
+}
+
+/// Hash a text with 64-bit FNV-1a, as 16 hexadecimal digits (see `ContentHasher`)
+fn content_hash(text: &str) -> String {
+    let mut hasher = ContentHasher::new();
+    hasher.update(text.as_bytes());
+    hasher.to_hex()
+}
+
+/// Hash everything written to it with 64-bit FNV-1a, e.g. a JSON while it is serialized.
+/// Unlike std's DefaultHasher, the algorithm is fixed so the hash is stable between builds.
+struct ContentHasher {
+    hash: u64,
+}
+
+impl ContentHasher {
+    fn new() -> ContentHasher {
+        ContentHasher {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash = (self.hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// The hash as 16 hexadecimal digits
+    fn to_hex(&self) -> String {
+        format!("{:016x}", self.hash)
+    }
+}
+
+impl Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether the server must keep the DOT/SVG in memory instead of writing them in `data/`.
+/// Enabled with `SIOSTAM_IN_MEMORY=true`, for read-only filesystems.
+fn in_memory_mode() -> bool {
+    env_flag("SIOSTAM_IN_MEMORY")
+}
+
+/// Where each build gets a folder of its own for the DOT and SVG files, `data` by default.
+/// Set with `SIOSTAM_BUILD_DIR`, preferably on the same filesystem as `data/`
+fn get_build_dir() -> String {
+    env::var("SIOSTAM_BUILD_DIR").unwrap_or_else(|_| "data".to_owned())
+}
+
+/// Atomically replace `destination` by `source`. Across filesystems, the file is first copied
+/// next to `destination`, so that the readers never see it half written
+fn replace_file(source: &Path, destination: &Path) -> Result<(), CustomError> {
+    let error = |err: &dyn std::fmt::Display| {
+        CustomError::new(format!(
+            "While replacing `{}` by `{}`: {}",
+            destination.display(),
+            source.display(),
+            err
+        ))
+    };
+    if fs::rename(source, destination).is_ok() {
+        return Ok(());
+    }
+
+    let folder = destination
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut copy = tempfile::NamedTempFile::new_in(folder).map_err(|err| error(&err))?;
+    let mut file = fs::File::open(source).map_err(|err| error(&err))?;
+    io::copy(&mut file, &mut copy).map_err(|err| error(&err))?;
+    copy.persist(destination).map_err(|err| error(&err))?;
+    Ok(())
+}
+
+/// Make sure graphviz produced something looking like an SVG, rather than serving an empty graph
+pub fn check_svg(svg: &str) -> Result<(), CustomError> {
+    let svg = svg.trim_start();
+    if svg.is_empty() {
+        return Err(CustomError::new(
+            "Graphviz produced an empty svg. Is graphviz installed?".to_owned(),
+        ));
+    }
+    if !svg.starts_with("<svg") && !svg.starts_with("<?xml") {
+        return Err(CustomError::new(format!(
+            "Graphviz produced an invalid svg starting with: {}",
+            svg.chars().take(80).collect::<String>()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Intrinsic size of the SVG, as written by graphviz on the root element
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SvgDimensions {
+    width: Option<String>,
+    height: Option<String>,
+    view_box: Option<String>,
+}
+
+impl SvgDimensions {
+    /// Read the width, height and viewBox attributes of the root <svg> element
+    pub fn parse(svg: &str) -> SvgDimensions {
+        // Isolate the opening tag of the root element
+        let tag = match svg.find("<svg") {
+            Some(start) => {
+                let tag = &svg[start..];
+                &tag[..tag.find('>').unwrap_or(tag.len())]
+            }
+            None => return SvgDimensions::default(),
+        };
+
+        SvgDimensions {
+            width: find_attribute(tag, "width"),
+            height: find_attribute(tag, "height"),
+            view_box: find_attribute(tag, "viewBox"),
+        }
+    }
+}
+
+/// Get the value of a double-quoted attribute inside an XML tag
+fn find_attribute(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(pattern.as_str())? + pattern.len();
+    let length = tag[start..].find('"')?;
+    Some(tag[start..start + length].to_owned())
 }
 
 impl GraphRepresentation {
-    pub fn from(graph: Graph) -> Result<GraphRepresentation, CustomError> {
-        // JSON representation
-        let json = graph.to_json().map_err(|err| {
-            CustomError::new(format!("While constructing json representation: {}", err))
+    pub fn from(graph: Graph, render: &RenderConfig) -> Result<GraphRepresentation, CustomError> {
+        GraphRepresentation::from_cancellable(graph, render, &Cancellation::default())
+    }
+
+    /// Same as `from`, graphviz being killed once `cancellation` is cancelled
+    pub fn from_cancellable(
+        graph: Graph,
+        render: &RenderConfig,
+        cancellation: &Cancellation,
+    ) -> Result<GraphRepresentation, CustomError> {
+        // Hash of the JSON representation, without keeping it: it is streamed on demand
+        let started = Instant::now();
+        let graphviz = use_graphviz(render);
+        let mut hasher = ContentHasher::new();
+        serde_json::to_writer(&mut hasher, &graph).map_err(|err| {
+            CustomError::new(format!("While hashing json representation: {}", err))
         })?;
+        let content_hash = hasher.to_hex();
+
+        // DOT and SVG representations, unless only the JSON is used
+        let (dot, svg) = if render.disable_svg {
+            (None, None)
+        } else {
+            info!("Proceeding to generate the dot representation.");
+            let started = Instant::now();
+            let dot = graph.to_dot(render).map_err(|err| {
+                CustomError::new(format!("While generating dot representation: {}", err))
+            })?;
+            info!(
+                "Generated the dot representation in {:.2?}",
+                started.elapsed()
+            );
+
+            info!("Proceeding to generate the svg representation.");
+            let started = Instant::now();
+            cancellation.check()?;
+            let svg = if !graphviz {
+                render_native_svg(&graph)
+            } else if in_memory_mode() {
+                render_svg_from_dot_cancellable(dot.as_str(), None, cancellation)?
+            } else {
+                GraphRepresentation::svg_through_files(dot.as_str(), cancellation)?
+            };
+            info!(
+                "{} generated the svg representation in {:.2?}",
+                if graphviz {
+                    "Graphviz"
+                } else {
+                    "The built-in layout"
+                },
+                started.elapsed()
+            );
+            check_svg(svg.as_str())?;
+
+            (Some(dot), Some(svg))
+        };
+
+        info!("Finished the representations in {:.2?}.", started.elapsed());
+
+        let svg_dimensions = svg
+            .as_ref()
+            .map(|svg| SvgDimensions::parse(svg.as_str()))
+            .unwrap_or_default();
+        let issues = GraphIssues::from(&graph);
+        Ok(GraphRepresentation {
+            graph: Arc::new(graph),
+            content_hash,
+            dot,
+            svg,
+            svg_dimensions,
+            issues,
+            graphviz,
+            engine_svgs: Mutex::new(HashMap::new()),
+        })
+    }
 
-        // DOT representation
-        info!("Proceeding to generate the dot file.");
-        graph.output_to_dot("data/output.dot").map_err(|err| {
+    /// Let graphviz render the DOT in a folder of its own, then replace data/output.dot and
+    /// data/output.dot.svg. Two builds at the same time never write in the same files, and the
+    /// outputs are only replaced by complete ones. Each file is replaced atomically on its own:
+    /// in between, the new DOT is next to the previous SVG
+    fn svg_through_files(dot: &str, cancellation: &Cancellation) -> Result<String, CustomError> {
+        let build_dir = get_build_dir();
+        let folder = fs::create_dir_all(&build_dir)
+            .and_then(|_| {
+                tempfile::Builder::new()
+                    .prefix(".build-")
+                    .tempdir_in(&build_dir)
+            })
+            .map_err(|err| {
+                CustomError::new(format!(
+                    "While creating a build folder in `{}`: {}",
+                    build_dir, err
+                ))
+            })?;
+
+        let dot_path = folder.path().join("output.dot");
+        fs::write(&dot_path, dot).map_err(|err| {
             CustomError::new(format!(
-                "While reading generating dot file `data/output.dot`: {}",
+                "While writing dot file `{}`: {}",
+                dot_path.display(),
                 err
             ))
         })?;
 
-        // SVG representation
-        info!("Proceeding to generate the svg file.");
-        generate_file_from_dot("data/output.dot");
-        let svg = fs::read_to_string("data/output.dot.svg").map_err(|err| {
+        generate_file_from_dot_cancellable(&dot_path.to_string_lossy(), cancellation)?;
+        let svg_path = folder.path().join("output.dot.svg");
+        let svg = fs::read_to_string(&svg_path).map_err(|err| {
             CustomError::new(format!(
-                "While reading svg file `data/output.dot.svg`: {}",
+                "While reading svg file `{}`: {}",
+                svg_path.display(),
                 err
             ))
         })?;
+        check_svg(svg.as_str())?;
+
+        replace_file(&dot_path, Path::new("data/output.dot"))?;
+        replace_file(&svg_path, Path::new("data/output.dot.svg"))?;
+        Ok(svg)
+    }
+
+    /// Render the DOT with another graphviz layout engine, once per engine and version of the
+    /// graph. None if the SVG generation is disabled, an error if graphviz is not used
+    pub fn svg_with_engine(&self, engine: &str) -> Result<Option<String>, CustomError> {
+        let dot = match self.dot.as_ref() {
+            Some(dot) => dot,
+            None => return Ok(None),
+        };
+        if !self.graphviz {
+            return Err(CustomError::new(format!(
+                "The `{}` engine needs graphviz, which is disabled by `disable_graphviz` or not \
+                 available: the svg is drawn with the built-in layout",
+                engine
+            )));
+        }
+
+        if let Some(svg) = self.lock_engine_svgs()?.get(engine) {
+            return Ok(Some(svg.clone()));
+        }
+
+        // No lock while rendering: the other engines stay available in the meantime.
+        // Two requests for a new engine may both render it, the first one is kept
+        let started = Instant::now();
+        let svg = render_svg_from_dot(dot.as_str(), Some(engine))?;
+        check_svg(svg.as_str())?;
+        info!(
+            "Graphviz generated the svg representation with `{}` in {:.2?}",
+            engine,
+            started.elapsed()
+        );
+        let svg = self
+            .lock_engine_svgs()?
+            .entry(engine.to_owned())
+            .or_insert(svg)
+            .clone();
+        Ok(Some(svg))
+    }
+
+    fn lock_engine_svgs(&self) -> Result<MutexGuard<'_, HashMap<String, String>>, CustomError> {
+        self.engine_svgs
+            .lock()
+            .map_err(|err| CustomError::new(format!("While accessing the rendered SVGs: {}", err)))
+    }
 
-        info!("Finished.");
+    /// The DOT source of the SVG, if the SVG generation is enabled
+    pub fn dot(&self) -> Option<String> {
+        self.dot.clone()
+    }
 
-        Ok(GraphRepresentation { json, svg })
+    pub fn content_hash(&self) -> String {
+        self.content_hash.clone()
     }
 
-    pub fn json(&self) -> String {
-        self.json.clone()
+    pub fn graph(&self) -> &Graph {
+        &self.graph
     }
 
-    pub fn svg(&self) -> String {
+    pub fn shared_graph(&self) -> Arc<Graph> {
+        self.graph.clone()
+    }
+
+    pub fn svg(&self) -> Option<String> {
         self.svg.clone()
     }
+
+    pub fn svg_dimensions(&self) -> SvgDimensions {
+        self.svg_dimensions.clone()
+    }
+
+    pub fn issues(&self) -> GraphIssues {
+        self.issues.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem_mapping::test_utils::{
+        config_with, config_with_folder, config_with_folder_and, graph, graph_with, source,
+    };
+
+    #[test]
+    fn test_include_systems() {
+        let graph = graph_with(
+            &[
+                "[system]\nid = \"parent\"\n[[subsystem]]\nid = \"a\"",
+                r#"
+                stored_in_system = "parent"
+                [system]
+                id = "child"
+                [[subsystem]]
+                id = "b"
+                dependency = [{ id = "a" }, { id = "c" }]
+                [[subsystem]]
+                id = "c"
+                "#,
+                "[system]\nid = \"grandchild\"\n[[subsystem]]\nid = \"d\"",
+                "stored_in_system = \"child\"\n[[subsystem]]\nid = \"e\"",
+            ],
+            &config_with("include_systems = [\"child\"]"),
+        );
+
+        let systems: Vec<&str> = graph.systems.iter().map(|s| s.id.as_str()).collect();
+        let subsystems: Vec<&str> = graph.subsystems.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(systems, vec!["child"]);
+        assert_eq!(subsystems, vec!["b", "c", "e"]);
+        assert!(graph.systems[0].parent_system.is_none());
+
+        // Only the dependency towards a kept subsystem remains, with its new index
+        let dependencies: Vec<(&str, Option<usize>)> = graph.subsystems[0]
+            .dependencies
+            .iter()
+            .map(|d| (d.subsystem.id(), d.subsystem.index()))
+            .collect();
+        assert_eq!(dependencies, vec![("c", Some(1))]);
+    }
+
+    #[test]
+    fn test_check_clone_folders() {
+        let config: SiostamConfig = toml::from_str(
+            r#"
+            suffix = "subsystems.toml"
+            [[targets]]
+            url = "https://github.com/org-a/api.git"
+            [[targets]]
+            url = "https://gitlab.com/org-b/api"
+            [[targets]]
+            url = "https://github.com/org-a/web.git"
+            [[targets]]
+            folder = "api"
+            "#,
+        )
+        .unwrap();
+
+        let err = check_clone_folders(&config).unwrap_err();
+        assert!(err.message.ends_with(
+            "- data/api: https://github.com/org-a/api.git, https://gitlab.com/org-b/api"
+        ));
+        assert!(check_clone_folders(&config_with("")).is_ok());
+    }
+
+    #[test]
+    fn test_dependency_on_system() {
+        let graph = graph(&[
+            "[system]\nid = \"storage\"\n[[subsystem]]\nid = \"db\"",
+            "[[subsystem]]\nid = \"api\"\ndependency = [{ id = \"storage\" }, { id = \"db\" }]",
+        ]);
+
+        // The subsystem is found first, the system only when no subsystem has the id
+        let dependencies: Vec<(Option<usize>, Option<usize>)> = graph.subsystems[1]
+            .dependencies
+            .iter()
+            .map(|d| (d.subsystem.index(), d.system.index()))
+            .collect();
+        assert_eq!(dependencies, vec![(None, Some(0)), (Some(0), None)]);
+        assert!(find_unresolved_references(&graph).is_empty());
+        let json = serde_json::to_string(&graph.subsystems[1].dependencies).unwrap();
+        assert_eq!(json.matches("\"system\"").count(), 1);
+
+        // The edge goes to the invisible anchor of the cluster, whatever the layout engine
+        let dot = graph.to_dot(&RenderConfig::default()).unwrap();
+        assert!(dot.contains("\"__anchor__storage\" [\n      id = \"system_storage_anchor\";"));
+        assert!(dot.contains("\"api\" -> \"__anchor__storage\" [\n    id = \"api_to_system_storage\";\n    lhead = \"cluster__storage\";"));
+        assert!(dot.contains("id = \"api_to_db\";\n  ]"));
+    }
+
+    /// The files merged without the checks of `source_to_graph`, to link them by hand
+    fn unlinked(contents: &[&str]) -> Graph {
+        merge_all_files(
+            contents
+                .iter()
+                .enumerate()
+                .map(|(i, content)| source(&format!("{}.subsystems.toml", i), content))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_reconstruct_links_dangling_ids() {
+        let mut graph = unlinked(&[concat!(
+            "stored_in_system = \"missing_parent\"\n",
+            "[system]\nid = \"back\"\n",
+            "[[subsystem]]\nid = \"api\"\ndependencies = [{ id = \"missing\" }]",
+        )]);
+        reconstruct_links(&mut graph);
+
+        // The ids are kept for the issues and the clients, without index
+        let parent = graph.systems[0].parent_system.as_ref().unwrap();
+        assert_eq!((parent.id(), parent.index()), ("missing_parent", None));
+        let dependency = &graph.subsystems[0].dependencies[0];
+        assert_eq!(dependency.subsystem.id(), "missing");
+        assert_eq!(dependency.subsystem.index(), None);
+        assert_eq!(dependency.system.index(), None);
+        assert_eq!(graph.subsystems[0].out_degree, 0);
+        assert_eq!(
+            graph.subsystems[0].parent_system.as_ref().unwrap().index(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_links_duplicate_ids() {
+        let mut graph = unlinked(&[
+            "[system]\nid = \"shared\"\n[[subsystem]]\nid = \"db\"",
+            "[system]\nid = \"shared\"\n[[subsystem]]\nid = \"db\"",
+            concat!(
+                "stored_in_system = \"shared\"\n",
+                "[[subsystem]]\nid = \"api\"\ndependencies = [{ id = \"db\" }, { id = \"shared\" }]",
+            ),
+        ]);
+        reconstruct_links(&mut graph);
+
+        // Both duplicates are kept and the references lead to the last one declared
+        assert_eq!(graph.systems.len(), 2);
+        assert_eq!(graph.subsystems.len(), 3);
+        let api = &graph.subsystems[2];
+        assert_eq!(api.parent_system.as_ref().unwrap().index(), Some(1));
+        assert_eq!(api.dependencies[0].subsystem.index(), Some(1));
+        assert_eq!(api.dependencies[1].system.index(), Some(1));
+        assert_eq!(graph.subsystems[0].in_degree, 0);
+        assert_eq!(graph.subsystems[1].in_degree, 1);
+
+        // Every reference, parent links included, resolves to the last duplicate declared:
+        // the subsystem of the first file ends up in the system of the second one
+        assert_eq!(
+            graph.subsystems[0].parent_system.as_ref().unwrap().index(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_neighborhood() {
+        let graph = graph(&[
+            "[system]\nid = \"storage\"\n[[subsystem]]\nid = \"db\"\n[[subsystem]]\nid = \"cache\"",
+            "[[subsystem]]\nid = \"web\"\ndependency = [{ id = \"api\" }]",
+            "[[subsystem]]\nid = \"api\"\ndependency = [{ id = \"auth\" }, { id = \"storage\" }]",
+            "[[subsystem]]\nid = \"auth\"\ndependency = [{ id = \"db\" }]",
+        ]);
+        let ids = |graph: &Graph| -> Vec<String> {
+            graph.subsystems.iter().map(|s| s.id.clone()).collect()
+        };
+
+        let around = graph.neighborhood("api", 1, Direction::Both).unwrap();
+        assert_eq!(ids(&around), vec!["web", "api", "auth"]);
+        // The targeted system is kept, without its subsystems
+        assert_eq!(around.systems.len(), 1);
+        assert_eq!(around.subsystems[1].dependencies[1].system.index(), Some(0));
+        // The dependency of auth leads out of the neighborhood
+        assert!(around.subsystems[2].dependencies.is_empty());
+
+        let below = graph
+            .neighborhood("api", 2, Direction::Dependencies)
+            .unwrap();
+        assert_eq!(ids(&below), vec!["db", "api", "auth"]);
+        assert_eq!(
+            below.subsystems[2].dependencies[0].subsystem.index(),
+            Some(0)
+        );
+
+        let above = graph.neighborhood("db", 5, Direction::Dependants).unwrap();
+        assert_eq!(ids(&above), vec!["db", "web", "api", "auth"]);
+
+        assert!(graph.neighborhood("unknown", 1, Direction::Both).is_none());
+    }
+
+    #[test]
+    fn test_how_to_category() {
+        let graph = graph(&[concat!(
+            "[[subsystem]]\nid = \"api\"\n",
+            "how_to = [{ url = \"https://a\", category = \"runbook\" }, { url = \"https://b\" }]",
+        )]);
+
+        let json = graph.to_compact_json().unwrap();
+        assert!(json.contains(r#"{"url":"https://a","text":"https://a","category":"runbook"}"#));
+        assert!(json.contains(r#"{"url":"https://b","text":"https://b","category":null}"#));
+    }
+
+    #[test]
+    fn test_how_to_deep_link() {
+        let graph = graph(&[concat!(
+            "[[subsystem]]\nid = \"api\"\n",
+            "how_to = [{ url = \"https://a/doc.md#old\", anchor = \"setup\" },",
+            " { url = \"https://b/main.rs\", line = 42 }, { url = \"https://c#kept\" }]",
+        )]);
+
+        let json = graph.to_compact_json().unwrap();
+        assert!(json.contains(r#""url":"https://a/doc.md#setup""#));
+        assert!(json.contains(r#""url":"https://b/main.rs#L42""#));
+        assert!(json.contains(r#""url":"https://c#kept""#));
+    }
+
+    #[test]
+    fn test_anonymize_paths() {
+        let config = config_with("anonymize_paths = true");
+        let graph = graph_with(
+            &[
+                "[system]\nid = \"a\"\n[[subsystem]]\nid = \"b\"",
+                "[[subsystem]]\nid = \"c\"",
+            ],
+            &config,
+        );
+
+        let json = graph.to_compact_json().unwrap();
+        assert!(!json.contains("subsystems.toml"));
+        assert!(!json.contains("\"test\""));
+        assert_eq!(graph.systems[0].repo_name, graph.subsystems[1].repo_name);
+        assert_eq!(graph.systems[0].path, graph.subsystems[0].path);
+        assert_ne!(graph.subsystems[0].path, graph.subsystems[1].path);
+        assert_ne!(graph.systems[0].repo_name, content_hash("test"));
+    }
+
+    #[test]
+    fn test_group_by_id_prefix() {
+        let config = config_with("group_by_id_prefix = \".\"");
+        let graph = graph_with(
+            &[
+                "[[subsystem]]\nid = \"pay.api\"\n[[subsystem]]\nid = \"pay.db\"",
+                "[system]\nid = \"auth.core\"\n[[subsystem]]\nid = \"auth.token\"",
+                "[[subsystem]]\nid = \"flat\"",
+            ],
+            &config,
+        );
+
+        assert_eq!(
+            graph.hierarchy_to_json().unwrap(),
+            concat!(
+                r#"{"systems":[{"id":"auth","name":"auth","systems":[{"id":"auth.core","#,
+                r#""name":"auth.core","systems":[],"subsystems":[{"id":"auth.token","name":"auth.token"}]}],"#,
+                r#""subsystems":[]},{"id":"pay","name":"pay","systems":[],"subsystems":["#,
+                r#"{"id":"pay.api","name":"pay.api"},{"id":"pay.db","name":"pay.db"}]}],"#,
+                r#""subsystems":[{"id":"flat","name":"flat"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_inherit_how_tos() {
+        let graph = graph_with(
+            &[
+                concat!(
+                    "[system]\nid = \"billing\"\n",
+                    "[[system.how_to]]\nurl = \"https://wiki/billing\"\ntext = \"Runbook\"\n",
+                    "[[subsystem]]\nid = \"invoices\"\n",
+                    "[[subsystem]]\nid = \"payments\"\n",
+                    "[[subsystem.how_to]]\nurl = \"https://wiki/payments\"\ntext = \"Own\"",
+                ),
+                "stored_in_system = \"billing\"\n[system]\nid = \"taxes\"\n[[subsystem]]\nid = \"vat\"",
+            ],
+            &config_with("inherit_howtos = true"),
+        );
+        let urls = |how_to: &[HowTo]| how_to.iter().map(|h| h.url.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            urls(&graph.subsystems[0].how_to),
+            vec!["https://wiki/billing"]
+        );
+        assert_eq!(
+            urls(&graph.subsystems[1].how_to),
+            vec!["https://wiki/payments"]
+        );
+        // Through the child system, which inherits as well
+        assert_eq!(urls(&graph.systems[1].how_to), vec!["https://wiki/billing"]);
+        assert_eq!(
+            urls(&graph.subsystems[2].how_to),
+            vec!["https://wiki/billing"]
+        );
+    }
+
+    #[test]
+    fn test_how_tos_to_json() {
+        let graph = graph(&[concat!(
+            "[system]\nid = \"a\"\nhow_to = [{ url = \"https://a\", category = \"runbook\" }]\n",
+            "[[subsystem]]\nid = \"b\"\nname = \"B\"\nhow_to = [{ url = \"https://b\", text = \"Doc\" }]",
+        )]);
+
+        assert_eq!(
+            graph.how_tos_to_json().unwrap(),
+            concat!(
+                r#"[{"url":"https://a","text":"https://a","category":"runbook","kind":"system","id":"a","name":"a"},"#,
+                r#"{"url":"https://b","text":"Doc","category":null,"kind":"subsystem","id":"b","name":"B"}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_degrees() {
+        let graph = graph(&[
+            "[system]\nid = \"storage\"\n[[subsystem]]\nid = \"db\"",
+            "[[subsystem]]\nid = \"api\"\ndependency = [{ id = \"db\" }, { id = \"storage\" }, { id = \"unknown\" }]",
+            "[[subsystem]]\nid = \"web\"\ndependency = [{ id = \"db\" }]",
+        ]);
+
+        let degrees: Vec<(usize, usize)> = graph
+            .subsystems
+            .iter()
+            .map(|s| (s.in_degree, s.out_degree))
+            .collect();
+        assert_eq!(degrees, vec![(2, 0), (0, 2), (0, 1)]);
+    }
+
+    #[test]
+    fn test_unassigned_cluster() {
+        let graph = graph(&[
+            "[system]\nid = \"a\"\n[[subsystem]]\nid = \"b\"",
+            "[[subsystem]]\nid = \"c\"",
+            "stored_in_system = \"unknown\"\n[[subsystem]]\nid = \"d\"",
+        ]);
+        let render: RenderConfig = toml::from_str("unassigned_cluster = true").unwrap();
+        let dot = graph.to_dot(&render).unwrap();
+
+        let cluster = dot.find("subgraph \"cluster____unassigned\"").unwrap();
+        assert!(dot[cluster..].contains("label = \"Unassigned\";"));
+        assert!(dot[cluster..].find("\"c\" [").is_some());
+        assert!(dot[cluster..].find("\"d\" [").is_some());
+        assert!(dot[..cluster].find("\"b\" [").is_some());
+    }
+
+    #[test]
+    fn test_construct_with_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("a.subsystems.toml"),
+            "[[subsystem]]\nid = \"api\"\ndependency = [{ id = \"db\" }]\n[[subsystem]]\nid = \"database\"",
+        )
+        .unwrap();
+        let config: SiostamConfig = toml::from_str(&format!(
+            "suffix = \"subsystems.toml\"\n[[targets]]\nfolder = {:?}",
+            dir.path().to_string_lossy()
+        ))
+        .unwrap();
+
+        // The links are reconstructed after the transform, with the new ids
+        let graph = Graph::construct_from_config_with(&config, |graph| {
+            graph.subsystems[1].id = "db".to_owned();
+        })
+        .unwrap();
+        assert_eq!(
+            graph.subsystems[0].dependencies[0].subsystem.index(),
+            Some(1)
+        );
+        assert_eq!(graph.subsystems[1].in_degree, 1);
+    }
+
+    #[test]
+    fn test_description_file_inside_repository() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.env"), "TOKEN=1").unwrap();
+        let repo = outside.path().join("repo");
+        fs::create_dir_all(repo.join("docs")).unwrap();
+        fs::write(repo.join("docs/auth.md"), "Signs the users in").unwrap();
+
+        let description = |description_file: &str| {
+            let mut source = source(
+                "docs/auth.subsystems.toml",
+                &format!(
+                    "[system]\nid = \"auth\"\ndescription_file = {:?}",
+                    description_file
+                ),
+            );
+            source.directory = Some(repo.join("docs"));
+            source.root = Some(repo.clone());
+            source
+                .extract_system()
+                .map(|s| s.unwrap().description.unwrap())
+        };
+
+        assert_eq!(description("auth.md").unwrap(), "Signs the users in");
+        // Absolute paths and `..` are refused, even when they would come back inside
+        for path in ["../../secret.env", "/etc/passwd", "./../docs/auth.md"].iter() {
+            let err = description(path).unwrap_err().to_string();
+            assert!(
+                err.contains("it must be a relative path inside the repository"),
+                "{}",
+                err
+            );
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(
+                outside.path().join("secret.env"),
+                repo.join("docs/link.md"),
+            )
+            .unwrap();
+            let err = description("link.md").unwrap_err().to_string();
+            assert!(err.contains("it must be a relative path inside the repository"));
+        }
+    }
+
+    #[test]
+    fn test_require_dependency_why() {
+        let graph = graph(&[concat!(
+            "[[subsystem]]\nid = \"api\"\n",
+            "dependencies = [{ id = \"db\", why = \"Stores the users\" }, { id = \"cache\" }]\n",
+            "[[subsystem]]\nid = \"db\"",
+        )]);
+
+        let err = check_dependencies_why(&graph).unwrap_err().to_string();
+        assert!(err.starts_with("1 dependency(ies) without `why`"));
+        assert!(err.contains("- `api` -> `cache` in test/0.subsystems.toml"));
+        assert!(!err.contains("`db`"));
+
+        let explained = graph_with(
+            &["[[subsystem]]\nid = \"api\"\ndependencies = [{ id = \"db\", why = \"Users\" }]"],
+            &config_with("require_dependency_why = true"),
+        );
+        assert!(check_dependencies_why(&explained).is_ok());
+    }
+
+    #[test]
+    fn test_require_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().to_string_lossy();
+
+        // Only a warning by default
+        assert!(Graph::construct_from_config(&config_with_folder(&folder)).is_ok());
+        let err =
+            Graph::construct_from_config(&config_with_folder_and(&folder, "require_files = true"))
+                .unwrap_err();
+        assert!(err.to_string().contains("is the suffix right?"));
+    }
+
+    #[test]
+    fn test_invalid_files_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("a.subsystems.toml"),
+            "[[subsystem]]\nid = \"a\"",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.subsystems.toml"), "[[subsystem]\nid = ").unwrap();
+        let folder = dir.path().to_string_lossy();
+
+        // The valid file is still in the graph, the other one in the issues
+        let graph = Graph::construct_from_config(&config_with_folder(&folder)).unwrap();
+        assert_eq!(graph.subsystems.len(), 1);
+        let invalid_files = GraphIssues::from(&graph).invalid_files;
+        assert_eq!(invalid_files.len(), 1);
+        assert_eq!(invalid_files[0].path, "b.subsystems.toml");
+
+        let err =
+            Graph::construct_from_config(&config_with_folder_and(&folder, "strict_files = true"))
+                .unwrap_err();
+        assert!(err.to_string().contains("b.subsystems.toml"));
+    }
+
+    #[test]
+    fn test_cancelled_construction() {
+        let config: SiostamConfig =
+            toml::from_str("suffix = \"subsystems.toml\"\n[[targets]]\nfolder = \"./src\"")
+                .unwrap();
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+
+        let mut cache = TargetCache::default();
+        let err = Graph::construct_incrementally(&config, &mut cache, &cancellation).unwrap_err();
+        assert!(err.to_string().contains("cancelled"));
+    }
+
+    #[test]
+    fn test_hierarchy_to_json() {
+        let graph = graph(&[
+            "[system]\nid = \"parent\"\n[[subsystem]]\nid = \"a\"",
+            "stored_in_system = \"parent\"\n[system]\nid = \"child\"\n[[subsystem]]\nid = \"b\"",
+            "[[subsystem]]\nid = \"c\"",
+        ]);
+
+        assert_eq!(
+            graph.hierarchy_to_json().unwrap(),
+            concat!(
+                r#"{"systems":[{"id":"parent","name":"parent","systems":["#,
+                r#"{"id":"child","name":"child","systems":[],"subsystems":[{"id":"b","name":"b"}]}],"#,
+                r#""subsystems":[{"id":"a","name":"a"}]}],"subsystems":[{"id":"c","name":"c"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        // Reference values of FNV-1a 64
+        assert_eq!(content_hash(""), "cbf29ce484222325");
+        assert_eq!(content_hash("a"), "af63dc4c8601ec8c");
+        assert_ne!(content_hash("{\"a\":1}"), content_hash("{\"a\":2}"));
+
+        // Hashed while serialized, as the JSON served
+        let graph = graph(&["[[subsystem]]\nid = \"a\""]);
+        let render = RenderConfig {
+            disable_svg: true,
+            ..RenderConfig::default()
+        };
+        let representation = GraphRepresentation::from(graph.clone(), &render).unwrap();
+        assert_eq!(
+            representation.content_hash(),
+            content_hash(graph.to_compact_json().unwrap().as_str())
+        );
+    }
+
+    #[test]
+    fn test_built_in_layout_without_engines() {
+        let graph = graph(&["[[subsystem]]\nid = \"a\""]);
+        let render = RenderConfig {
+            disable_graphviz: true,
+            ..RenderConfig::default()
+        };
+        let representation = GraphRepresentation::from(graph, &render).unwrap();
+
+        assert!(representation.svg().unwrap().starts_with("<svg"));
+        assert!(representation.svg_with_engine("dot").is_err());
+    }
+
+    #[test]
+    fn test_svg_dimensions_parse() {
+        let svg = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg width="428pt" height="260pt"
+ viewBox="0.00 0.00 428.00 260.00" xmlns="http://www.w3.org/2000/svg">
+<g id="graph0" class="graph" width="1pt"></g></svg>"#;
+
+        assert_eq!(
+            SvgDimensions::parse(svg),
+            SvgDimensions {
+                width: Some("428pt".to_owned()),
+                height: Some("260pt".to_owned()),
+                view_box: Some("0.00 0.00 428.00 260.00".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_svg() {
+        assert!(check_svg("<?xml version=\"1.0\"?>\n<svg></svg>").is_ok());
+        assert!(check_svg("\n<svg></svg>").is_ok());
+        assert!(check_svg("").is_err());
+        assert!(check_svg("Error: syntax error in line 1").is_err());
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let folder = tempfile::tempdir().unwrap();
+        let (source, destination) = (folder.path().join("new"), folder.path().join("output"));
+        fs::write(&source, "new").unwrap();
+        fs::write(&destination, "old").unwrap();
+
+        replace_file(&source, &destination).unwrap();
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "new");
+        assert!(!source.exists());
+        assert!(replace_file(&source, &destination).is_err());
+    }
+
+    #[test]
+    fn test_svg_dimensions_parse_empty() {
+        assert_eq!(SvgDimensions::parse(""), SvgDimensions::default());
+    }
+
+    /// From the files of a local folder to the JSON and the DOT, with the fixtures of
+    /// `tests/fixtures/pipeline`. The walk order is not fixed: the items are looked up by id.
+    #[test]
+    fn test_config_to_dot_pipeline() {
+        let folder = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pipeline");
+        let graph = Graph::construct_from_config(&config_with_folder(folder)).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+        let find = |kind: &str, id: &str| -> serde_json::Value {
+            json[kind]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|item| item["id"] == id)
+                .unwrap_or_else(|| panic!("No {} `{}`", kind, id))
+                .clone()
+        };
+        let system_index = |id: &str| graph.systems.iter().position(|s| s.id == id).unwrap();
+        let subsystem_index = |id: &str| graph.subsystems.iter().position(|s| s.id == id).unwrap();
+
+        // Only the files with the suffix are read
+        assert_eq!(graph.systems.len(), 3);
+        assert_eq!(graph.subsystems.len(), 3);
+
+        // The systems are nested with stored_in_system, the subsystems are in their file system
+        assert!(find("systems", "platform")["parent_system"].is_null());
+        assert_eq!(
+            find("systems", "back-end")["parent_system"]["index"],
+            system_index("platform")
+        );
+        assert_eq!(
+            find("subsystems", "db")["parent_system"]["index"],
+            system_index("back-end")
+        );
+        assert_eq!(
+            find("subsystems", "db")["path"],
+            "back-end/back-end.subsystems.toml"
+        );
+
+        // The dependencies lead to a subsystem, to a whole system or to nothing
+        let api = find("subsystems", "api");
+        assert_eq!(
+            api["dependencies"][0]["subsystem"]["index"],
+            subsystem_index("db")
+        );
+        assert_eq!(api["dependencies"][0]["why"], "Stores the orders");
+        let web = find("subsystems", "web");
+        assert_eq!(
+            web["dependencies"][0]["subsystem"]["index"],
+            subsystem_index("api")
+        );
+        assert!(web["dependencies"][1]["subsystem"]["index"].is_null());
+        assert_eq!(
+            web["dependencies"][1]["system"]["index"],
+            system_index("back-end")
+        );
+        assert_eq!(web["dependencies"][1]["optional"], true);
+        assert!(web["dependencies"][2]["subsystem"]["index"].is_null());
+        assert!(web["dependencies"][2]["system"]["index"].is_null());
+        assert_eq!(find("subsystems", "db")["in_degree"], 1);
+        assert_eq!(graph.edge_count(), 3);
+
+        // The clusters are nested the same way, and the unknown dependency is not drawn
+        let dot = graph.to_dot(&RenderConfig::default()).unwrap();
+        let platform = dot.find("subgraph \"cluster__platform\"").unwrap();
+        let back_end = dot.find("  subgraph \"cluster__back-end\"").unwrap();
+        let db = dot.find("id = \"subsystem_db\";").unwrap();
+        assert!(platform < back_end && back_end < db);
+        assert!(dot.contains("\"api\" -> \"db\" ["));
+        assert!(dot.contains("id = \"web_to_api\";"));
+        assert!(dot.contains("lhead = \"cluster__back-end\";"));
+        assert!(!dot.contains("payments"));
+    }
 }
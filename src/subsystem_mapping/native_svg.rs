@@ -0,0 +1,304 @@
+use crate::subsystem_mapping::Graph;
+use crate::xml::escape_xml;
+use std::fmt::Write;
+
+/// Size of the boxes and the space between them, in pixels
+const NODE_HEIGHT: usize = 36;
+const MIN_NODE_WIDTH: usize = 80;
+const CHAR_WIDTH: usize = 7;
+const HORIZONTAL_GAP: usize = 24;
+const VERTICAL_GAP: usize = 60;
+const MARGIN: usize = 16;
+
+/// A subsystem and where it is drawn
+struct Node<'a> {
+    id: &'a str,
+    name: &'a str,
+    system: Option<&'a str>,
+    rank: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+}
+
+/// Draw the subsystems and their dependencies as an SVG, without graphviz.
+///
+/// It is a plain layered layout: every subsystem is placed one row below the lowest of the
+/// subsystems depending on it, the cycles being broken first, then each row is ordered to keep
+/// the edges short. The systems are not drawn as boxes, their name is written under the name of
+/// their subsystems. The SVG ids are the same as the ones of graphviz (`subsystem_<id>` and
+/// `<id_a>_to_<id_b>`).
+pub fn render_native_svg(graph: &Graph) -> String {
+    // The dependencies to a whole system lead to its first subsystem, as in the flat DOT
+    let mut edges = Vec::new();
+    for (a, subsystem) in graph.subsystems.iter().enumerate() {
+        for dependency in subsystem.dependencies.iter() {
+            let b = match dependency.subsystem.index() {
+                Some(b) => Some(b),
+                None => dependency
+                    .system
+                    .index()
+                    .and_then(|system| graph.first_subsystem_in(system))
+                    .and_then(|b| graph.subsystems.iter().position(|s| s.id == b.id)),
+            };
+            if let Some(b) = b.filter(|b| *b != a) {
+                edges.push((a, b, dependency.optional, dependency.deprecated.is_some()));
+            }
+        }
+    }
+
+    // Break the cycles with a depth-first walk, without recursion: an edge leading to a
+    // subsystem which is not finished yet closes a cycle. It is drawn, but left out of the ranks
+    let count = graph.subsystems.len();
+    let mut successors = vec![Vec::new(); count];
+    for (a, b, _, _) in edges.iter() {
+        successors[*a].push(*b);
+    }
+    let mut visited = vec![false; count];
+    let mut finish_order = vec![0; count];
+    let mut finished = 0;
+    for root in 0..count {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next)) = stack.last_mut() {
+            let node = *node;
+            match successors[node].get(*next) {
+                Some(&b) => {
+                    *next += 1;
+                    if !visited[b] {
+                        visited[b] = true;
+                        stack.push((b, 0));
+                    }
+                }
+                None => {
+                    finish_order[node] = finished;
+                    finished += 1;
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    // Longest path from the subsystems nobody depends on, in topological order: the remaining
+    // edges always lead to a subsystem finished earlier
+    let mut ranks = vec![0; count];
+    let mut topological: Vec<usize> = (0..count).collect();
+    topological.sort_by_key(|index| std::cmp::Reverse(finish_order[*index]));
+    for a in topological {
+        for b in successors[a].iter() {
+            if finish_order[*b] < finish_order[a] {
+                ranks[*b] = ranks[*b].max(ranks[a] + 1);
+            }
+        }
+    }
+
+    let mut nodes: Vec<Node> = graph
+        .subsystems
+        .iter()
+        .zip(ranks.iter())
+        .map(|(subsystem, rank)| {
+            let system = subsystem
+                .parent_system
+                .as_ref()
+                .and_then(|p| p.index())
+                .map(|i| graph.systems[i].name.as_str());
+            let longest = subsystem
+                .name
+                .chars()
+                .count()
+                .max(system.map(|s| s.chars().count()).unwrap_or(0));
+            Node {
+                id: subsystem.id.as_str(),
+                name: subsystem.name.as_str(),
+                system,
+                rank: *rank,
+                x: 0,
+                y: 0,
+                width: (longest * CHAR_WIDTH + 20).max(MIN_NODE_WIDTH),
+            }
+        })
+        .collect();
+
+    // Order each row by the average position of the subsystems depending on it, then by system
+    let row_count = ranks.iter().max().map(|r| r + 1).unwrap_or(0);
+    let mut rows: Vec<Vec<usize>> = vec![Vec::new(); row_count];
+    for (index, node) in nodes.iter().enumerate() {
+        rows[node.rank].push(index);
+    }
+    let mut positions = vec![0.0; count];
+    for row in rows.iter_mut() {
+        let barycenter = |index: usize| {
+            let above: Vec<f64> = edges
+                .iter()
+                .filter(|(a, b, _, _)| *b == index && nodes[*a].rank < nodes[index].rank)
+                .map(|(a, _, _, _)| positions[*a])
+                .collect();
+            if above.is_empty() {
+                f64::MAX
+            } else {
+                above.iter().sum::<f64>() / above.len() as f64
+            }
+        };
+        let mut keyed: Vec<(f64, usize)> = row.iter().map(|i| (barycenter(*i), *i)).collect();
+        keyed.sort_by(|(x, a), (y, b)| {
+            x.partial_cmp(y)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| nodes[*a].system.cmp(&nodes[*b].system))
+                .then_with(|| nodes[*a].name.cmp(nodes[*b].name))
+        });
+        *row = keyed.into_iter().map(|(_, i)| i).collect();
+        for (position, index) in row.iter().enumerate() {
+            positions[*index] = position as f64;
+        }
+    }
+
+    // The rows are centered on the widest one
+    let row_widths: Vec<usize> = rows
+        .iter()
+        .map(|row| {
+            row.iter().map(|i| nodes[*i].width).sum::<usize>()
+                + HORIZONTAL_GAP * row.len().saturating_sub(1)
+        })
+        .collect();
+    let width = row_widths.iter().max().unwrap_or(&0) + 2 * MARGIN;
+    let rows_height = match row_count {
+        0 => 0,
+        _ => row_count * (NODE_HEIGHT + VERTICAL_GAP) - VERTICAL_GAP,
+    };
+    let height = rows_height + 2 * MARGIN;
+    for (rank, row) in rows.iter().enumerate() {
+        let mut x = (width - row_widths[rank]) / 2;
+        for index in row.iter() {
+            let node = &mut nodes[*index];
+            node.x = x;
+            node.y = MARGIN + rank * (NODE_HEIGHT + VERTICAL_GAP);
+            x += node.width + HORIZONTAL_GAP;
+        }
+    }
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}pt\" height=\"{h}pt\" viewBox=\"0 0 {w} {h}\">",
+        w = width,
+        h = height
+    );
+    svg.push_str(concat!(
+        "<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" ",
+        "markerWidth=\"8\" markerHeight=\"8\" orient=\"auto-start-reverse\">",
+        "<path d=\"M 0 0 L 10 5 L 0 10 z\"/></marker></defs>\n",
+    ));
+    svg.push_str(
+        "<g id=\"graph0\" class=\"graph\" font-family=\"Helvetica,sans-Serif\" font-size=\"10\">\n",
+    );
+
+    for (a, b, optional, deprecated) in edges.iter() {
+        let (a, b) = (&nodes[*a], &nodes[*b]);
+        let color = if *deprecated {
+            "red"
+        } else if *optional {
+            "gray"
+        } else {
+            "black"
+        };
+        let dash = if *optional {
+            " stroke-dasharray=\"5,3\""
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            svg,
+            "<g id=\"{}_to_{}\" class=\"edge\"><line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"{} marker-end=\"url(#arrow)\"/></g>",
+            escape_xml(a.id),
+            escape_xml(b.id),
+            a.x + a.width / 2,
+            a.y + NODE_HEIGHT,
+            b.x + b.width / 2,
+            b.y,
+            color,
+            dash
+        );
+    }
+
+    for node in nodes.iter() {
+        let center = node.x + node.width / 2;
+        let _ = write!(
+            svg,
+            "<g id=\"subsystem_{}\" class=\"node\"><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"white\" stroke=\"black\"/>",
+            escape_xml(node.id),
+            escape_xml(node.id),
+            node.x,
+            node.y,
+            node.width,
+            NODE_HEIGHT
+        );
+        match node.system {
+            Some(system) => {
+                let _ = write!(
+                    svg,
+                    "<text x=\"{c}\" y=\"{}\" text-anchor=\"middle\">{}</text><text x=\"{c}\" y=\"{}\" text-anchor=\"middle\" fill=\"gray\" font-size=\"8\">{}</text>",
+                    node.y + 16,
+                    escape_xml(node.name),
+                    node.y + 29,
+                    escape_xml(system),
+                    c = center
+                );
+            }
+            None => {
+                let _ = write!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                    center,
+                    node.y + 22,
+                    escape_xml(node.name)
+                );
+            }
+        }
+        svg.push_str("</g>\n");
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystem_mapping::test_utils::graph;
+
+    #[test]
+    fn test_layered_layout() {
+        let graph = graph(&[concat!(
+            "[system]\nid = \"back\"\nname = \"Back-end\"\n",
+            "[[subsystem]]\nid = \"web\"\nname = \"Web\"\ndependencies = [{ id = \"api\" }]\n",
+            "[[subsystem]]\nid = \"api\"\nname = \"A <API>\"\ndependencies = [{ id = \"web\" }]",
+        )]);
+        let svg = render_native_svg(&graph);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("<g id=\"subsystem_web\" class=\"node\">"));
+        assert!(svg.contains("<g id=\"web_to_api\" class=\"edge\">"));
+        assert!(svg.contains(">A &lt;API&gt;</text>"));
+        assert!(svg.contains(">Back-end</text>"));
+        // The cycle does not push the subsystems further down than one row each
+        assert!(svg.contains("<rect x=\"16\" y=\"112\""));
+    }
+
+    #[test]
+    fn test_cycle_among_other_subsystems() {
+        let graph = graph(&[concat!(
+            "[[subsystem]]\nid = \"a\"\ndependencies = [{ id = \"b\" }]\n",
+            "[[subsystem]]\nid = \"b\"\ndependencies = [{ id = \"a\" }]\n",
+            "[[subsystem]]\nid = \"c\"\n[[subsystem]]\nid = \"d\"\n[[subsystem]]\nid = \"e\"",
+        )]);
+        let svg = render_native_svg(&graph);
+
+        // Two rows: the cycle is broken instead of pushing its subsystems to the bottom
+        let height = 2 * (NODE_HEIGHT + VERTICAL_GAP) - VERTICAL_GAP + 2 * MARGIN;
+        assert!(svg.contains(format!("height=\"{}pt\"", height).as_str()));
+        assert!(svg.contains("<g id=\"b_to_a\" class=\"edge\">"));
+    }
+}
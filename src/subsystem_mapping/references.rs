@@ -18,6 +18,17 @@ pub struct ReferenceByIndex<T> {
     phantom: PhantomData<T>,
 }
 
+/// Implemented by hand: the referenced type does not have to be Clone
+impl<T> Clone for ReferenceByIndex<T> {
+    fn clone(&self) -> Self {
+        ReferenceByIndex {
+            id: self.id.clone(),
+            index: self.index,
+            phantom: PhantomData,
+        }
+    }
+}
+
 impl<T> ReferenceByIndex<T> {
     /// The reference does not store the index right away because we have to wait for all systems
     /// and subsystems to be there. Instead, we reconstruct the link using find_index_in later.
@@ -34,8 +45,23 @@ impl<T> ReferenceByIndex<T> {
         self.index = indexes.get(&self.id).map(|i| *i);
     }
 
+    /// Forget the index, when the id is resolved by another reference
+    pub fn clear_index(&mut self) {
+        self.index = None;
+    }
+
+    /// Simple getter for the id of the referenced item
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     /// Simple getter for the index. May be None if the referenced item is missing
     pub fn index(&self) -> Option<usize> {
         self.index
     }
+
+    /// Whether no item has been found for the id, to leave it out of the JSON
+    pub fn is_unresolved(&self) -> bool {
+        self.index.is_none()
+    }
 }
@@ -0,0 +1,56 @@
+//! Helpers to build graphs in tests without any file on disk
+
+use crate::config::SiostamConfig;
+use crate::subsystem_mapping::{source_to_graph, Graph, SubsystemFileSource};
+
+/// A configuration with no target and the default options
+pub fn config() -> SiostamConfig {
+    config_with("")
+}
+
+/// A configuration with no target and the given options, written in TOML
+pub fn config_with(options: &str) -> SiostamConfig {
+    toml::from_str(&format!(
+        "suffix = \"subsystems.toml\"\ntargets = []\n{}",
+        options
+    ))
+    .expect("Invalid test configuration")
+}
+
+/// A configuration with a single local `folder` target and the default options
+pub fn config_with_folder(folder: &str) -> SiostamConfig {
+    config_with_folder_and(folder, "")
+}
+
+/// A configuration with a single local `folder` target and the given options, written in TOML
+pub fn config_with_folder_and(folder: &str, options: &str) -> SiostamConfig {
+    toml::from_str(&format!(
+        "suffix = \"subsystems.toml\"\n{}\n[[targets]]\nfolder = {:?}",
+        options, folder
+    ))
+    .expect("Invalid test configuration")
+}
+
+/// Parse the content of a subsystem file as if it was stored at `path` in the `test` repo
+pub fn source(path: &str, content: &str) -> SubsystemFileSource {
+    let mut source: SubsystemFileSource =
+        toml::from_str(content).expect("Invalid test subsystem file");
+    source.repo_name = Some("test".to_owned());
+    source.path = Some(path.to_owned());
+    source
+}
+
+/// Build a graph out of subsystem files contents, named `0.subsystems.toml`, `1.subsystems.toml`...
+pub fn graph(contents: &[&str]) -> Graph {
+    graph_with(contents, &config())
+}
+
+/// Same as `graph`, with a specific configuration
+pub fn graph_with(contents: &[&str], config: &SiostamConfig) -> Graph {
+    let sources = contents
+        .iter()
+        .enumerate()
+        .map(|(i, content)| source(&format!("{}.subsystems.toml", i), content))
+        .collect();
+    source_to_graph(sources, Vec::new(), config).expect("Invalid test graph")
+}
@@ -0,0 +1,162 @@
+use crate::config::read_config_in_workdir;
+use crate::subsystem_mapping::issues::GraphIssues;
+use crate::subsystem_mapping::Graph;
+use crate::xml::escape_xml;
+
+/// Result of a single check of the graph, a testcase in JUnit
+struct Check {
+    name: &'static str,
+    /// Each problem found, the check passes without any
+    failures: Vec<String>,
+    /// The check could not run, e.g. because the files can't be parsed
+    skipped: bool,
+}
+
+impl Check {
+    fn new(name: &'static str, failures: Vec<String>) -> Check {
+        Check {
+            name,
+            failures,
+            skipped: false,
+        }
+    }
+}
+
+/// Build the graph, check it and print the result as `text` or `junit`.
+/// Returns false if any check failed.
+pub fn run_validate(config_path: &str, format: &str) -> bool {
+    let checks = validate(config_path);
+    match format {
+        "junit" => print!("{}", to_junit(&checks)),
+        _ => print_text(&checks),
+    }
+    checks.iter().all(|check| check.failures.is_empty())
+}
+
+/// The parsing comes first: without a graph, the other checks are skipped.
+/// The invalid files left out of the graph fail the parsing as well
+fn validate(config_path: &str) -> Vec<Check> {
+    let graph = read_config_in_workdir(config_path)
+        .map_err(|err| err.to_string())
+        .and_then(|config| Graph::construct_from_config(&config).map_err(|err| err.to_string()));
+    let graph = match graph {
+        Ok(graph) => graph,
+        Err(err) => {
+            let skipped = |name| Check {
+                name,
+                failures: Vec::new(),
+                skipped: true,
+            };
+            return vec![
+                Check::new("parse", vec![err]),
+                skipped("duplicate_ids"),
+                skipped("unresolved_references"),
+                skipped("cycles"),
+            ];
+        }
+    };
+
+    let issues = GraphIssues::from(&graph);
+    let duplicates = issues
+        .duplicate_ids
+        .iter()
+        .chain(issues.ids_shared_by_system_and_subsystem.iter())
+        .map(|d| format!("{} `{}` declared in {}", d.kind, d.id, d.files.join(", ")))
+        .collect();
+    let unresolved = issues
+        .unresolved_references
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    let cycles = issues
+        .cycles
+        .iter()
+        .map(|cycle| format!("cycle between {}", cycle.join(" -> ")))
+        .collect();
+    let invalid_files = issues.invalid_files.iter().map(|f| f.to_string()).collect();
+    vec![
+        Check::new("parse", invalid_files),
+        Check::new("duplicate_ids", duplicates),
+        Check::new("unresolved_references", unresolved),
+        Check::new("cycles", cycles),
+    ]
+}
+
+/// Same look as the doctor checklist
+fn print_text(checks: &[Check]) {
+    for check in checks.iter() {
+        if check.skipped {
+            println!("[SKIP] {}", check.name);
+        } else if check.failures.is_empty() {
+            println!("[ OK ] {}", check.name);
+        } else {
+            for failure in check.failures.iter() {
+                println!("[FAIL] {}: {}", check.name, failure);
+            }
+        }
+    }
+}
+
+/// One testsuite with a testcase per check, understood by Jenkins and GitLab CI
+fn to_junit(checks: &[Check]) -> String {
+    let failures = checks.iter().filter(|c| !c.failures.is_empty()).count();
+    let skipped = checks.iter().filter(|c| c.skipped).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"siostam\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+        checks.len(),
+        failures,
+        skipped
+    ));
+    for check in checks.iter() {
+        let name = escape_xml(check.name);
+        if check.skipped {
+            xml.push_str(&format!(
+                "  <testcase classname=\"siostam\" name=\"{}\">\n    <skipped/>\n  </testcase>\n",
+                name
+            ));
+        } else if check.failures.is_empty() {
+            xml.push_str(&format!(
+                "  <testcase classname=\"siostam\" name=\"{}\"/>\n",
+                name
+            ));
+        } else {
+            xml.push_str(&format!(
+                "  <testcase classname=\"siostam\" name=\"{}\">\n",
+                name
+            ));
+            for failure in check.failures.iter() {
+                let failure = escape_xml(failure);
+                xml.push_str(&format!(
+                    "    <failure message=\"{}\">{}</failure>\n",
+                    failure, failure
+                ));
+            }
+            xml.push_str("  </testcase>\n");
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_junit_output() {
+        let checks = vec![
+            Check::new("parse", Vec::new()),
+            Check::new("cycles", vec!["cycle between a -> <b>".to_owned()]),
+        ];
+        let xml = to_junit(&checks);
+
+        assert!(
+            xml.contains("<testsuite name=\"siostam\" tests=\"2\" failures=\"1\" skipped=\"0\">")
+        );
+        assert!(xml.contains("<testcase classname=\"siostam\" name=\"parse\"/>"));
+        assert!(xml.contains(
+            "<failure message=\"cycle between a -&gt; &lt;b&gt;\">cycle between a -&gt; &lt;b&gt;</failure>"
+        ));
+    }
+}
@@ -0,0 +1,10 @@
+//! Helpers shared by the XML outputs: the built-in SVG and the JUnit report
+
+/// Escape a text to put it in an XML element or between double or single quotes in an attribute
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}